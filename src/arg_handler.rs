@@ -3,7 +3,8 @@ extern crate clap;
 use self::clap::{Arg, ArgMatches, App};
 
 use app::*;
-use bf::Options;
+use brainfuck_rs_quick::{Arithmetic, Boundary, CellWidth, Eof, Options};
+use brainfuck_rs_quick::bf::{MEM_SIZE, PASSES};
 
 
 
@@ -43,6 +44,79 @@ impl<'a: 'b, 'b> ArgHandler<'a> {
                 .long("pretty")
                 .alias("prettify")
                 .help("Pretify described program logic"))
+			.arg(Arg::with_name("eof")
+                .long("eof")
+                .value_name("BEHAVIOUR")
+                .possible_values(&["unchanged", "zero", "minus-one"])
+                .default_value("unchanged")
+                .help("Cell value when reading at the end of input"))
+			.arg(Arg::with_name("cell-size")
+                .long("cell-size")
+                .value_name("BITS")
+                .possible_values(&["8", "16", "32", "64"])
+                .default_value("8")
+                .help("Width of memory cells, output writes the low 8 bits"))
+			.arg(Arg::with_name("arithmetic")
+                .long("arithmetic")
+                .value_name("MODE")
+                .possible_values(&["wrap", "saturate", "trap"])
+                .default_value("wrap")
+                .help("How memory cell values overflow"))
+			.arg(Arg::with_name("tape-size")
+                .long("tape-size")
+                .value_name("CELLS")
+                .default_value("30000")
+                .validator(is_cells)
+                .help("Number of memory cells on the tape"))
+			.arg(Arg::with_name("tape-limit")
+                .long("tape-limit")
+                .value_name("CELLS")
+                .validator(is_cells)
+                .help("Grow the tape on demand, up to the given number of cells"))
+			.arg(Arg::with_name("tape-left")
+                .long("tape-left")
+                .value_name("BOUNDARY")
+                .possible_values(&["clamp", "wrap", "error", "grow"])
+                .default_value("clamp")
+                .help("What happens when the pointer moves past the left end"))
+			.arg(Arg::with_name("tape-right")
+                .long("tape-right")
+                .value_name("BOUNDARY")
+                .possible_values(&["clamp", "wrap", "error", "grow"])
+                .default_value("grow")
+                .help("What happens when the pointer moves past the right end"))
+			.arg(Arg::with_name("fuel")
+                .long("fuel")
+                .value_name("STEPS")
+                .validator(is_steps)
+                .help("Stop the program after executing the given number of operations"))
+			.arg(Arg::with_name("opt-level")
+                .short("O")
+                .long("opt-level")
+                .value_name("LEVEL")
+                .possible_values(&["0", "1", "2", "3"])
+                .default_value("2")
+                .help("Optimization level, selecting the optimization passes to run"))
+			.arg(Arg::with_name("enable-pass")
+                .long("enable-pass")
+                .value_name("PASS")
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(PASSES)
+                .help("Run the given optimization pass, regardless of the level"))
+			.arg(Arg::with_name("disable-pass")
+                .long("disable-pass")
+                .value_name("PASS")
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(PASSES)
+                .help("Don't run the given optimization pass, regardless of the level"))
+			.arg(Arg::with_name("strict")
+                .long("strict")
+                .help("Only optimize in ways that keep program behaviour, including infinite loops"))
+			.arg(Arg::with_name("verify-optimizations")
+                .long("verify-optimizations")
+                .help("Run the unoptimized program alongside, stop where the optimized program diverges"))
             .get_matches();
 
         // Instantiate
@@ -77,13 +151,129 @@ impl<'a: 'b, 'b> ArgHandler<'a> {
 		self.matches.is_present("pretty")
 	}
 
+	/// Get the end of input behaviour.
+	pub fn eof(&self) -> Eof {
+		self.matches.value_of("eof")
+            .and_then(|eof| eof.parse().ok())
+            .unwrap_or(Eof::Unchanged)
+	}
+
+	/// Get the memory cell width.
+	pub fn cell_width(&self) -> CellWidth {
+		self.matches.value_of("cell-size")
+            .and_then(|bits| bits.parse().ok())
+            .unwrap_or(CellWidth::Bits8)
+	}
+
+	/// Get the memory cell arithmetic mode.
+	pub fn arithmetic(&self) -> Arithmetic {
+		self.matches.value_of("arithmetic")
+            .and_then(|arithmetic| arithmetic.parse().ok())
+            .unwrap_or(Arithmetic::Wrap)
+	}
+
+	/// Get the number of memory cells on the tape.
+	pub fn tape_size(&self) -> usize {
+		self.matches.value_of("tape-size")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(MEM_SIZE)
+	}
+
+	/// Get the number of memory cells the tape may grow to, if growable.
+	pub fn tape_limit(&self) -> Option<usize> {
+		self.matches.value_of("tape-limit")
+            .and_then(|limit| limit.parse().ok())
+	}
+
+	/// Get the boundary at the left end of the tape.
+	pub fn tape_left(&self) -> Boundary {
+		self.matches.value_of("tape-left")
+            .and_then(|boundary| boundary.parse().ok())
+            .unwrap_or(Boundary::Clamp)
+	}
+
+	/// Get the boundary at the right end of the tape.
+	pub fn tape_right(&self) -> Boundary {
+		self.matches.value_of("tape-right")
+            .and_then(|boundary| boundary.parse().ok())
+            .unwrap_or(Boundary::Grow)
+	}
+
+	/// Get the number of operations to execute, if limited.
+	pub fn fuel(&self) -> Option<u64> {
+		self.matches.value_of("fuel")
+            .and_then(|fuel| fuel.parse().ok())
+	}
+
+	/// Get the optimization level.
+	pub fn opt_level(&self) -> u8 {
+		self.matches.value_of("opt-level")
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(2)
+	}
+
+	/// Get the names of optimization passes to enable.
+	pub fn enable_passes(&self) -> Vec<String> {
+		self.matches.values_of("enable-pass")
+            .map(|passes| passes.map(|pass| pass.into()).collect())
+            .unwrap_or_default()
+	}
+
+	/// Get the names of optimization passes to disable.
+	pub fn disable_passes(&self) -> Vec<String> {
+		self.matches.values_of("disable-pass")
+            .map(|passes| passes.map(|pass| pass.into()).collect())
+            .unwrap_or_default()
+	}
+
+	/// Check whether to only optimize in ways that keep program behaviour.
+	pub fn strict(&self) -> bool {
+		self.matches.is_present("strict")
+	}
+
+	/// Check whether to verify optimizations against the unoptimized program.
+	pub fn verify(&self) -> bool {
+		self.matches.is_present("verify-optimizations")
+	}
+
     /// Create an interpreter options object, based on the CLI arguments.
     pub fn as_options(&self) -> Options {
-        Options::default(
+        let mut options = Options::default(
             self.buffer(),
             self.profile(),
             self.describe(),
             self.pretty(),
-        )
+        );
+        options.eof = self.eof();
+        options.cell_width = self.cell_width();
+        options.arithmetic = self.arithmetic();
+        options.tape_size = self.tape_size();
+        options.tape_limit = self.tape_limit();
+        options.tape_left = self.tape_left();
+        options.tape_right = self.tape_right();
+        options.fuel = self.fuel();
+        options.opt_level = self.opt_level();
+        options.enable_passes = self.enable_passes();
+        options.disable_passes = self.disable_passes();
+        options.fresh_memory = true;
+        options.strict = self.strict();
+        options.verify = self.verify();
+        options
+    }
+}
+
+/// Validate a number of memory cells given as argument.
+fn is_cells(cells: String) -> Result<(), String> {
+    match cells.parse::<usize>() {
+        Ok(cells) if cells > 0 => Ok(()),
+        _ => Err("must be a positive number of cells".into()),
+    }
+}
+
+/// Validate a number of operations to execute given as argument.
+fn is_steps(steps: String) -> Result<(), String> {
+    match steps.parse::<u64>() {
+        Ok(_) => Ok(()),
+        _ => Err("must be a number of operations".into()),
     }
 }
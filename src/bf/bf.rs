@@ -1,39 +1,26 @@
-use std::u8;
+use std::io;
 
-use profiler::Profiler;
+use super::{BfError, Exit, Memory, Options, Program};
 
-use super::{Interpreter, Memory, Options};
 
 
-
-/// Interpret a Brainfuck program from a string.
-/// Return the result string.
-pub fn bf(prog: &str, options: &Options) -> String {
+/// Interpret a Brainfuck program from a string with 8-bit memory cells,
+/// reading input from stdin.
+/// Return the exact output bytes, or an error if the program failed or ran out
+/// of fuel.
+pub fn bf(prog: &str, options: &Options) -> Result<Vec<u8>, BfError> {
     // Create application memory, and define an output vector
-    let mut memory = Memory::new();
+    let mut memory: Memory = Memory::from_options(options);
     let mut output: Vec<u8> = vec![];
 
-    // Interpret the program
-    let start = Interpreter::interpret(&mut prog.bytes(), &options);
-
-    // Describe program logic
-    if options.describe {
-        if options.pretty {
-            println!("{:#?}", start);
-        } else {
-            println!("{:?}", start);
-        }
-    }
+    // Compile and run the program
+    let exit = Program::compile(prog, options)?
+        .run(&mut memory, options, &mut io::stdin(), &mut output)?;
 
-    // Execute the program from the start and profile
-    let mut profiler = Profiler::new(options.profile);
-    start.execute(&mut memory, &options, &mut output);
-    if options.profile {
-        profiler.report("Executing");
+    match exit {
+        Exit::Finished => Ok(output),
+        Exit::OutOfFuel(suspension) => Err(BfError::OutOfFuel(suspension)),
     }
-
-    // Parse and output the string
-    String::from_utf8(output).unwrap()
 }
 
 
@@ -43,7 +30,23 @@ pub fn bf(prog: &str, options: &Options) -> String {
 #[test]
 fn test_hello_world() {
     assert_eq!(
-        bf("++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.<<+++++++++++++++.>.+++.------.--------.>+.>."),
-        "Hello World!\n",
+        bf(
+            "++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.<<+++++++++++++++.>.+++.------.--------.>+.>.",
+            &Options::default(true, false, false, false),
+        ).unwrap(),
+        b"Hello World!\n",
+    );
+}
+
+/// Test whether output bytes that are not valid UTF-8 are passed through
+/// unchanged.
+#[test]
+fn test_binary_output() {
+    assert_eq!(
+        bf(
+            "++++++++[>++++++++++++++++++++++++<-]>.+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.",
+            &Options::default(true, false, false, false),
+        ).unwrap(),
+        vec![0xC0, 0xFF],
     );
 }
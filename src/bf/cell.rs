@@ -0,0 +1,163 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+
+
+
+/// A memory cell value type.
+///
+/// Memory cells are unsigned integers of a fixed width, supporting the
+/// arithmetic the interpreter needs to modify them.
+///
+/// Program output writes the low 8 bits of a cell value, program input stores
+/// each read byte as is, zero extended to the cell width.
+pub trait Cell: Copy + Default + Eq + Debug + 'static {
+    /// The maximum cell value, with all bits set.
+    const MAX: Self;
+
+    /// The number of bits in a cell.
+    const BITS: u32;
+
+    /// Add the relative `amount`, wrapping around on overflow.
+    fn wrapping_inc(self, amount: isize) -> Self;
+
+    /// Add the relative `amount`, saturating on overflow.
+    fn saturating_inc(self, amount: isize) -> Self;
+
+    /// Add the relative `amount`, returning `None` on overflow.
+    fn checked_inc(self, amount: isize) -> Option<Self>;
+
+    /// Create a cell value from an input byte.
+    fn from_byte(byte: u8) -> Self;
+
+    /// Get the output byte for this cell value, being the low 8 bits.
+    fn to_byte(self) -> u8;
+
+    /// Get the cell value as 64-bit number.
+    fn to_u64(self) -> u64;
+
+    /// Create a cell value from the low bits of a 64-bit number.
+    fn from_u64(value: u64) -> Self;
+}
+
+/// Get the magnitude of the given relative `amount`.
+fn magnitude(amount: isize) -> u64 {
+    if amount >= 0 {
+        amount as u64
+    } else {
+        amount.wrapping_neg() as usize as u64
+    }
+}
+
+/// Implement the cell trait for an unsigned integer type.
+macro_rules! impl_cell {
+    ($type:ident) => {
+        impl Cell for $type {
+            const MAX: $type = $type::MAX;
+            const BITS: u32 = $type::BITS;
+
+            fn wrapping_inc(self, amount: isize) -> $type {
+                self.wrapping_add(amount as $type)
+            }
+
+            fn saturating_inc(self, amount: isize) -> $type {
+                let magnitude = magnitude(amount).min($type::MAX as u64) as $type;
+                if amount >= 0 {
+                    self.saturating_add(magnitude)
+                } else {
+                    self.saturating_sub(magnitude)
+                }
+            }
+
+            fn checked_inc(self, amount: isize) -> Option<$type> {
+                let magnitude = magnitude(amount);
+                if magnitude > $type::MAX as u64 {
+                    None
+                } else if amount >= 0 {
+                    self.checked_add(magnitude as $type)
+                } else {
+                    self.checked_sub(magnitude as $type)
+                }
+            }
+
+            fn from_byte(byte: u8) -> $type {
+                byte as $type
+            }
+
+            fn to_byte(self) -> u8 {
+                self as u8
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_u64(value: u64) -> $type {
+                value as $type
+            }
+        }
+    };
+}
+
+impl_cell!(u8);
+impl_cell!(u16);
+impl_cell!(u32);
+impl_cell!(u64);
+
+/// The width of memory cells, selecting the cell type to run a program with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellWidth {
+    /// 8-bit cells, `u8`.
+    Bits8,
+
+    /// 16-bit cells, `u16`.
+    Bits16,
+
+    /// 32-bit cells, `u32`.
+    Bits32,
+
+    /// 64-bit cells, `u64`.
+    Bits64,
+}
+
+impl CellWidth {
+    /// Get the number of bits in a cell.
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::Bits8 => u8::BITS,
+            CellWidth::Bits16 => u16::BITS,
+            CellWidth::Bits32 => u32::BITS,
+            CellWidth::Bits64 => u64::BITS,
+        }
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = ();
+
+    fn from_str(bits: &str) -> Result<CellWidth, ()> {
+        match bits {
+            "8" => Ok(CellWidth::Bits8),
+            "16" => Ok(CellWidth::Bits16),
+            "32" => Ok(CellWidth::Bits32),
+            "64" => Ok(CellWidth::Bits64),
+            _ => Err(()),
+        }
+    }
+}
+
+
+
+/// Test whether cell arithmetic respects the cell width.
+#[test]
+fn test_cell_arithmetic() {
+    assert_eq!(250u8.wrapping_inc(300), 38);
+    assert_eq!(250u16.wrapping_inc(300), 550);
+    assert_eq!(0u32.wrapping_inc(-1), u32::MAX);
+    assert_eq!(0u64.wrapping_inc(-1), u64::MAX);
+    assert_eq!(10u8.saturating_inc(-300), 0);
+    assert_eq!(10u16.saturating_inc(-300), 0);
+    assert_eq!(u64::MAX.saturating_inc(isize::MAX), u64::MAX);
+    assert_eq!(255u8.checked_inc(1), None);
+    assert_eq!(255u16.checked_inc(1), Some(256));
+    assert_eq!(0x1234u16.to_byte(), 0x34);
+}
@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::{Divergence, Suspension};
+
+
+
+/// A position in brainfuck program source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset, starting at zero.
+    pub offset: usize,
+
+    /// Line number, starting at one.
+    pub line: usize,
+
+    /// Column number in characters, starting at one.
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of the first byte in a program.
+    pub fn start() -> Position {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {} (byte {})", self.line, self.column, self.offset)
+    }
+}
+
+/// A range in brainfuck program source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// The position of the first byte in the range.
+    pub start: Position,
+
+    /// The position right after the last byte in the range.
+    pub end: Position,
+}
+
+impl Span {
+    /// Create a span covering both the given spans, and anything in between.
+    pub fn join(self, other: Span) -> Span {
+        Span {
+            start: if other.start.offset < self.start.offset { other.start } else { self.start },
+            end: if other.end.offset > self.end.offset { other.end } else { self.end },
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {} (bytes {}..{})",
+            self.start.line,
+            self.start.column,
+            self.start.offset,
+            self.end.offset,
+        )
+    }
+}
+
+/// An error that occurred while parsing a brainfuck program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A loop end `]` at the given position, without a matching loop start.
+    UnmatchedClose(Position),
+
+    /// A loop start `[` at the given position, that is never closed.
+    UnclosedOpen(Position),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnmatchedClose(pos) =>
+                write!(f, "unmatched ']' at {}", pos),
+            ParseError::UnclosedOpen(pos) =>
+                write!(f, "unclosed '[' opened at {}", pos),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// An error that occurred while running a brainfuck program.
+#[derive(Debug)]
+pub enum RunError {
+    /// Reading program input or writing program output failed.
+    Io(io::Error),
+
+    /// The memory pointer moved out of bounds, to the given first cell index
+    /// past the end of the tape.
+    /// Along with the source range of the operation that caused it, if known.
+    OutOfBounds(isize, Option<Span>),
+
+    /// A memory cell value overflowed, while arithmetic is trapping.
+    /// Along with the source range of the operation that caused it, if known.
+    Overflow(Option<Span>),
+
+    /// The optimized program diverged from the unoptimized program, while
+    /// verifying optimizations.
+    Diverged(Divergence),
+
+    /// The memory wasn't created with the options the program is run with.
+    MemoryMismatch,
+}
+
+impl RunError {
+    /// Attach the source range of the failed operation to this error,
+    /// if it doesn't have one yet.
+    pub fn at(self, span: Span) -> RunError {
+        match self {
+            RunError::OutOfBounds(index, None) => RunError::OutOfBounds(index, Some(span)),
+            RunError::Overflow(None) => RunError::Overflow(Some(span)),
+            err => err,
+        }
+    }
+}
+
+/// Format the source range an error occurred at, if known.
+fn fmt_at(f: &mut fmt::Formatter, span: Option<Span>) -> fmt::Result {
+    match span {
+        Some(span) => write!(f, " at {}", span),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunError::Io(ref err) =>
+                write!(f, "program I/O failed: {}", err),
+            RunError::OutOfBounds(index, span) => {
+                write!(f, "memory pointer moved out of bounds, to cell {}", index)?;
+                fmt_at(f, span)
+            },
+            RunError::Overflow(span) => {
+                write!(f, "memory cell value overflowed")?;
+                fmt_at(f, span)
+            },
+            RunError::Diverged(ref divergence) =>
+                write!(f, "optimized program diverged from the unoptimized program {}", divergence),
+            RunError::MemoryMismatch =>
+                write!(f, "memory doesn't match the tape and cell options the program runs with"),
+        }
+    }
+}
+
+impl Error for RunError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RunError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(err: io::Error) -> RunError {
+        RunError::Io(err)
+    }
+}
+
+/// An error that occurred while parsing or running a brainfuck program.
+#[derive(Debug)]
+pub enum BfError {
+    /// The program could not be parsed.
+    Parse(ParseError),
+
+    /// The program failed while running.
+    Run(RunError),
+
+    /// The program ran out of fuel, and was suspended.
+    OutOfFuel(Suspension),
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BfError::Parse(ref err) => err.fmt(f),
+            BfError::Run(ref err) => err.fmt(f),
+            BfError::OutOfFuel(ref suspension) =>
+                write!(f, "program ran out of fuel, {}", suspension),
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BfError::Parse(ref err) => Some(err),
+            BfError::Run(ref err) => Some(err),
+            BfError::OutOfFuel(_) => None,
+        }
+    }
+}
+
+impl From<ParseError> for BfError {
+    fn from(err: ParseError) -> BfError {
+        BfError::Parse(err)
+    }
+}
+
+impl From<RunError> for BfError {
+    fn from(err: RunError) -> BfError {
+        BfError::Run(err)
+    }
+}
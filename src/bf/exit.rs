@@ -0,0 +1,79 @@
+use std::fmt;
+
+use super::Span;
+
+
+
+/// The way running a program, or executing an operation, stopped.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Execution finished.
+    Finished,
+
+    /// Execution ran out of fuel, and was suspended.
+    /// The memory is left in the state it was suspended in, and execution may
+    /// be resumed from the given suspension.
+    OutOfFuel(Suspension),
+}
+
+/// The position a program was suspended at, when it ran out of fuel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suspension {
+    /// Indices of the operations to resume at, in each nested routine.
+    position: Vec<usize>,
+
+    /// The source range of the operation to resume at.
+    span: Option<Span>,
+}
+
+impl Suspension {
+    /// A suspension at the start of a program, running it from the start.
+    pub fn start() -> Suspension {
+        Suspension::at(vec![], None)
+    }
+
+    /// A suspension at the given position, see `position`.
+    pub(crate) fn at(position: Vec<usize>, span: Option<Span>) -> Suspension {
+        Suspension {
+            position,
+            span,
+        }
+    }
+
+    /// Get the position to resume at.
+    ///
+    /// This is the index of the operation to resume at in each nested
+    /// routine, starting at the program root. An index equal to the number of
+    /// routine operations resumes at the routine loop condition.
+    pub fn position(&self) -> &[usize] {
+        &self.position
+    }
+
+    /// Get the source range of the operation to resume at, if known.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Wrap this suspension in the routine it was suspended in, at the given
+    /// operation `index`.
+    pub(crate) fn within(mut self, index: usize) -> Suspension {
+        self.position.insert(0, index);
+        self
+    }
+
+    /// Attach the source range of the suspended operation, if this suspension
+    /// doesn't have one yet.
+    pub(crate) fn at_span(mut self, span: Span) -> Suspension {
+        self.span = self.span.or(Some(span));
+        self
+    }
+}
+
+impl fmt::Display for Suspension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "suspended at {}", span),
+            None => write!(f, "suspended at the start"),
+        }
+    }
+}
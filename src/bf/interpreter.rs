@@ -3,12 +3,53 @@ use std::str::Bytes;
 
 use profiler::Profiler;
 
-use super::optimize::optimize_routine;
+use super::optimize::PassManager;
 
-use super::{Op, Options};
+use super::{Arithmetic, Node, Op, Options, ParseError, Position, Span};
 
 
 
+/// A stream of brainfuck program bytes, that keeps track of the source
+/// position of each byte.
+struct Source<'a, 'b: 'a> {
+    /// The program bytes.
+    bytes: &'a mut Bytes<'b>,
+
+    /// The position of the next byte.
+    position: Position,
+}
+
+impl<'a, 'b> Source<'a, 'b> {
+    /// Create a source stream from the given program bytes.
+    fn new(bytes: &'a mut Bytes<'b>) -> Source<'a, 'b> {
+        Source {
+            bytes,
+            position: Position::start(),
+        }
+    }
+}
+
+impl<'a, 'b> Iterator for Source<'a, 'b> {
+    type Item = (u8, Position);
+
+    /// Get the next byte, along with its position.
+    fn next(&mut self) -> Option<(u8, Position)> {
+        let byte = self.bytes.next()?;
+        let position = self.position;
+
+        // Advance the position, don't count UTF-8 continuation bytes as column
+        self.position.offset += 1;
+        if byte == b'\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            self.position.column += 1;
+        }
+
+        Some((byte, position))
+    }
+}
+
 /// Brainfuck interpreter.
 ///
 /// This interpreter translates a stream of brainfuck program bytes into
@@ -17,20 +58,37 @@ pub struct Interpreter;
 
 impl Interpreter {
     /// Interpret a brainfuck program from the given byte stream.
-    /// Output a routine containing the whole state.
-    pub fn interpret(program: &mut Bytes, options: &Options) -> Op {
+    /// Output a routine node containing the whole state, optimized with the
+    /// passes selected in `options`.
+    ///
+    /// An error is returned if the program contains unbalanced brackets.
+    pub fn interpret(program: &mut Bytes, options: &Options) -> Result<Node, ParseError> {
+        Interpreter::interpret_with(program, options, &PassManager::from_options(options))
+    }
+
+    /// Interpret a brainfuck program from the given byte stream.
+    /// Output a routine node containing the whole state, optimized with the
+    /// given `passes`.
+    ///
+    /// An error is returned if the program contains unbalanced brackets.
+    pub fn interpret_with(
+        program: &mut Bytes,
+        options: &Options,
+        passes: &PassManager,
+    ) -> Result<Node, ParseError> {
         // Start a profiler
         let mut profiler = Profiler::new(options.profile);
 
-        // Interpret
-        let start = Interpreter::interpret_routine(program, false);
+        // Interpret and optimize
+        let mut start = Interpreter::interpret_routine(&mut Source::new(program), None, options)?;
+        passes.run(&mut start, options);
 
         // Report timings
         if options.profile {
             profiler.report("Interpreting and optimizing");
         }
 
-        start
+        Ok(start)
     }
 
     /// Interpret the given stream of bytes into a routine.
@@ -39,18 +97,26 @@ impl Interpreter {
     ///
     /// The byte stream should be given to `bytes`.
     ///
-    /// If `cond` is `true`, this routine is loopable, `false` if it isn't.
-    fn interpret_routine(bytes: &mut Bytes, cond: bool) -> Op {
+    /// If `open` is the position of a loop start, this routine is loopable.
+    /// If it is `None`, it isn't.
+    /// The `options` the program is run with select how operations combine.
+    fn interpret_routine(
+        bytes: &mut Source,
+        open: Option<Position>,
+        options: &Options,
+    ) -> Result<Node, ParseError> {
         // Interpret the contained routine operations
-        let ops = Interpreter::interpret_vec(bytes);
+        let cond = open.is_some();
+        let ops = Interpreter::interpret_vec(bytes, open, options)?;
 
-        // Optimize the routine
-        if let Some(opt) = optimize_routine(cond, &ops) {
-            return opt;
-        }
+        // The routine spans from its loop start up to and including its end
+        let span = Span {
+            start: open.unwrap_or_else(Position::start),
+            end: bytes.position,
+        };
 
         // Wrap the oprations in a routine as normal
-        Op::Routine(ops, cond)
+        Ok(Node::new(Op::Routine(ops, cond), span))
     }
 
     /// Interpret the given stream of bytes into a vector of operations.
@@ -59,7 +125,14 @@ impl Interpreter {
     /// or if a loop-end operator has been reached.
     ///
     /// The byte stream should be given to `bytes`.
-    fn interpret_vec(bytes: &mut Bytes) -> Vec<Op> {
+    /// The position of the loop start this vector is contained in should be
+    /// given to `open`, to check whether loop-end operators are balanced.
+    /// The `options` the program is run with select how operations combine.
+    fn interpret_vec(
+        bytes: &mut Source,
+        open: Option<Position>,
+        options: &Options,
+    ) -> Result<Vec<Node>, ParseError> {
         // Create an operations vector, and a workspace for the last operation
         // being worked on
         let mut ops = vec![];
@@ -68,11 +141,17 @@ impl Interpreter {
         // Interpret all bytes until we break
         loop {
             // Find the next byte to process, or break if the stream is emtpy
-            let byte = if let Some(byte) = bytes.next() {
-                byte
+            let (byte, position) = if let Some(next) = bytes.next() {
+                next
+            } else if let Some(open) = open {
+                return Err(ParseError::UnclosedOpen(open));
             } else {
                 break;
             };
+            let span = Span {
+                start: position,
+                end: bytes.position,
+            };
 
             // Process the byte
             match byte {
@@ -81,6 +160,8 @@ impl Interpreter {
                     &mut workspace,
                     &mut ops,
                     1,
+                    span,
+                    options,
                 ),
 
                 // Seek down
@@ -88,6 +169,8 @@ impl Interpreter {
                     &mut workspace,
                     &mut ops,
                     -1,
+                    span,
+                    options,
                 ),
 
                 // Increase memory cell value
@@ -95,6 +178,8 @@ impl Interpreter {
                     &mut workspace,
                     &mut ops,
                     1,
+                    span,
+                    options,
                 ),
 
                 // Decrease memory cell value
@@ -102,20 +187,22 @@ impl Interpreter {
                     &mut workspace,
                     &mut ops,
                     -1,
+                    span,
+                    options,
                 ),
 
                 // Output the value of the current memory cell
                 b'.' => {
                     // Commit and add a new operator
                     Interpreter::commit(&mut workspace, &mut ops, None);
-                    ops.push(Op::Output);
+                    ops.push(Node::new(Op::Output, span));
                 },
 
                 // Read user input
                 b',' => {
                     // Commit and add a new operator
                     Interpreter::commit(&mut workspace, &mut ops, None);
-                    ops.push(Op::Input);
+                    ops.push(Node::new(Op::Input, span));
                 },
 
                 // Start a conditional loop
@@ -123,12 +210,15 @@ impl Interpreter {
                     // Commit and add a new conditional routine
                     Interpreter::commit(&mut workspace, &mut ops, None);
                     ops.push(
-                        Interpreter::interpret_routine(bytes, true),
+                        Interpreter::interpret_routine(bytes, Some(position), options)?,
                     );
                 },
 
                 // End a conditional loop, finish this operation vector
-                b']' => break,
+                b']' if open.is_some() => break,
+
+                // End a conditional loop that was never started
+                b']' => return Err(ParseError::UnmatchedClose(position)),
 
                 // Unrecognized operation, skip
                 _ => continue,
@@ -136,11 +226,11 @@ impl Interpreter {
         }
 
         // Commit the last workspace operation
-        if let Some(op) = workspace {
-            ops.push(op);
+        if let Some(node) = workspace {
+            ops.push(node);
         }
 
-        ops
+        Ok(ops)
     }
 
     /// Commit the given workspace in the given.
@@ -154,10 +244,10 @@ impl Interpreter {
     ///
     /// You may want to consider using `None` as `fresh` option,
     /// to reset the workspace.
-    fn commit(workspace: &mut Option<Op>, ops: &mut Vec<Op>, fresh: Option<Op>) {
+    fn commit(workspace: &mut Option<Node>, ops: &mut Vec<Node>, fresh: Option<Node>) {
         // Take the workspace item, put it in the list
-        if let Some(op) = mem::replace(workspace, fresh) {
-            ops.push(op);
+        if let Some(node) = mem::replace(workspace, fresh) {
+            ops.push(node);
         }
     }
 
@@ -172,25 +262,37 @@ impl Interpreter {
     ///
     /// If the workspace was compatible, the workspace will be left uncommitted
     /// for possible further optimizations in upcomming instructions.
+    /// Seeks of opposite direction are only compatible if the pointer moves
+    /// exactly, as it may be clamped, wrapped or go out of bounds at an end of
+    /// the tape in between.
     ///
     /// The `workspace` is committed to `ops`.
+    /// The source range of the instruction should be given to `span`.
+    /// The `options` the program is run with define the ends of the tape.
     fn process_workspace_seek(
-        workspace: &mut Option<Op>,
-        ops: &mut Vec<Op>,
+        workspace: &mut Option<Node>,
+        ops: &mut Vec<Node>,
         amount: isize,
+        span: Span,
+        options: &Options,
     ) {
         // Determine whether to combine to an existing workspace,
         // or to commit and define a new operator workspace
         match *workspace {
             // Combine with the workspace operation
-            Some(Op::Seek(ref mut current)) => *current += amount,
+            Some(Node { op: Op::Seek(ref mut current), span: ref mut current_span })
+                if current.signum() == amount.signum() || options.exact(None, *current) =>
+            {
+                *current += amount;
+                *current_span = current_span.join(span);
+            },
 
             // Commit the workspace, start working on a new seek operator
             _ => Interpreter::commit(
                 workspace,
                 ops,
                 Some(
-                    Op::Seek(amount),
+                    Node::new(Op::Seek(amount), span),
                 ),
             ),
         }
@@ -207,27 +309,392 @@ impl Interpreter {
     ///
     /// If the workspace was compatible, the workspace will be left uncommitted
     /// for possible further optimizations in upcomming instructions.
+    /// Increments of opposite sign are only compatible if cell values wrap,
+    /// as saturating or trapping depends on the intermediate value.
     ///
     /// The `workspace` is committed to `ops`.
+    /// The source range of the instruction should be given to `span`.
+    /// The `options` the program is run with select the arithmetic.
     fn process_workspace_inc(
-        workspace: &mut Option<Op>,
-        ops: &mut Vec<Op>,
+        workspace: &mut Option<Node>,
+        ops: &mut Vec<Node>,
         amount: isize,
+        span: Span,
+        options: &Options,
     ) {
         // Determine whether to combine to an existing workspace,
         // or to commit and define a new operator workspace
         match *workspace {
             // Combine with the workspace operation
-            Some(Op::Inc(ref mut current)) => *current += amount,
+            Some(Node { op: Op::Inc(ref mut current), span: ref mut current_span })
+                if options.arithmetic == Arithmetic::Wrap || current.signum() == amount.signum() =>
+            {
+                *current += amount;
+                *current_span = current_span.join(span);
+            },
 
             // Commit the workspace, start working on a new increment operator
             _ => Interpreter::commit(
                 workspace,
                 ops,
                 Some(
-                    Op::Inc(amount),
+                    Node::new(Op::Inc(amount), span),
                 ),
             ),
         }
     }
 }
+
+
+
+/// Test whether unbalanced brackets are reported with their position.
+#[test]
+fn test_unbalanced_brackets() {
+    let options = Options::default(true, false, false, false);
+
+    assert_eq!(
+        Interpreter::interpret(&mut "+[-]\n>]".bytes(), &options).unwrap_err(),
+        ParseError::UnmatchedClose(Position { offset: 6, line: 2, column: 2 }),
+    );
+    assert_eq!(
+        Interpreter::interpret(&mut "+[[-]\n".bytes(), &options).unwrap_err(),
+        ParseError::UnclosedOpen(Position { offset: 1, line: 1, column: 2 }),
+    );
+}
+
+/// Test whether straight-line code moves the memory pointer at most once,
+/// unless the pointer may not move exactly at an end of the tape. Past an end
+/// that errors or grows, seeks are only deferred onto the next operation if it
+/// acts on the cell moved to.
+#[test]
+fn test_defer_seek() {
+    use super::Boundary;
+
+    let mut options = Options::default(true, false, false, false);
+    options.disable_passes = vec!["dead-loop".into(), "block".into()];
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ">+>+<<.>>>[<,]".bytes(), &options).unwrap()),
+        "Routine([IncAt(1, 1), IncAt(2, 1), Output, Seek(3), Routine([Seek(-1), Input], true)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<+>+".bytes(), &options).unwrap()),
+        "Routine([Seek(-1), Inc(1), IncAt(1, 1), Seek(1)], false)",
+    );
+
+    options.tape_right = Boundary::Clamp;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ">+>+<<.".bytes(), &options).unwrap()),
+        "Routine([Seek(1), Inc(1), Seek(1), Inc(1), Seek(-2), Output], false)",
+    );
+
+    options.tape_right = Boundary::Grow;
+    options.tape_left = Boundary::Grow;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ">+>+<<.>>>[<,]".bytes(), &options).unwrap()),
+        "Routine([IncAt(1, 1), IncAt(2, 1), Output, Seek(3), Routine([InputAt(-1), Seek(-1)], true)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<+>+".bytes(), &options).unwrap()),
+        "Routine([IncAt(-1, 1), Inc(1)], false)",
+    );
+
+    options.tape_left = Boundary::Error;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<+>+>+<".bytes(), &options).unwrap()),
+        "Routine([IncAt(-1, 1), Inc(1), IncAt(1, 1)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<+<[.]".bytes(), &options).unwrap()),
+        "Routine([IncAt(-1, 1), Seek(-2), Routine([Output], true)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<>+.".bytes(), &options).unwrap()),
+        "Routine([Input, Seek(-1), IncAt(1, 1), OutputAt(1), Seek(1)], false)",
+    );
+
+    // Growing the tape changes where the other end wraps to
+    options.tape_size = 1;
+    options.tape_limit = Some(usize::MAX);
+    options.tape_left = Boundary::Wrap;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ">+<<+>.".bytes(), &options).unwrap()),
+        "Routine([IncAt(1, 1), Seek(1), Seek(-2), Inc(1), OutputAt(1), Seek(1)], false)",
+    );
+}
+
+/// Test whether zeroing and adding to a cell is fused into setting it.
+#[test]
+fn test_set() {
+    let mut options = Options::default(true, false, false, false);
+    options.tape_limit = Some(usize::MAX);
+    options.disable_passes = vec!["dead-loop".into()];
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[-]+++++>[-]--<[-]".bytes(), &options).unwrap()),
+        "Routine([Set(5), SetAt(1, -2), Zero], false)",
+    );
+}
+
+/// Test whether no optimizations are applied at optimization level zero.
+#[test]
+fn test_opt_level_zero() {
+    let mut options = Options::default(true, false, false, false);
+    options.opt_level = 0;
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[-]+>[>]".bytes(), &options).unwrap()),
+        "Routine([Routine([Inc(-1)], true), Inc(1), Seek(1), Routine([Seek(1)], true)], false)",
+    );
+}
+
+/// Test whether loops and zeroing that can't have any effect are removed,
+/// relying on fresh memory only if programs run on it.
+#[test]
+fn test_dead_loop() {
+    use super::Boundary;
+
+    let mut options = Options::default(true, false, false, false);
+    options.disable_passes = vec!["canonicalize".into()];
+
+    // With a fixed tape, cells are only tracked while the pointer can't
+    // reach an end of it
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[-][-]".bytes(), &options).unwrap()),
+        "Routine([Zero], false)",
+    );
+    options.fresh_memory = true;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[.]+[-][-]>[-]<[>+<-]".bytes(), &options).unwrap()),
+        "Routine([Inc(1), Zero, Seek(1), Seek(-1)], false)",
+    );
+    options.tape_left = Boundary::Clamp;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<[-]>+[-].".bytes(), &options).unwrap()),
+        "Routine([Seek(-1), Zero, IncAt(1, 1), SetAt(1, 0), OutputAt(1), Seek(1)], false)",
+    );
+    options.tape_left = Boundary::Error;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<[-]>+[-].".bytes(), &options).unwrap()),
+        "Routine([SetAt(-1, 0), Inc(1), Zero, Output], false)",
+    );
+
+    options = Options::default(true, false, false, false);
+    options.tape_limit = Some(usize::MAX);
+    options.disable_passes = vec!["canonicalize".into()];
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[.]+[-][-]>[-]<[>+<-]".bytes(), &options).unwrap()),
+        "Routine([Routine([Output], true), Inc(1), Zero, SetAt(1, 0), AddAndZero(-1, [(1, 1)], Routine([Seek(1), Inc(1), Seek(-1), Inc(-1)], true))], false)",
+    );
+
+    options.fresh_memory = true;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "[.]+[-][-]>[-]<[>+<-]".bytes(), &options).unwrap()),
+        "Routine([Inc(1), Zero], false)",
+    );
+
+    // Loops after seeking past an end of the tape that errors are kept
+    options.tape_left = Boundary::Error;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "<[.]>".bytes(), &options).unwrap()),
+        "Routine([Seek(-1), Routine([Output], true), Seek(1)], false)",
+    );
+}
+
+/// Test whether equivalent spellings of add and zero loops are optimized into
+/// the same operation, only in the plain spelling unless cells wrap, keeping
+/// the lowest and highest cell reached.
+#[test]
+fn test_add_and_zero() {
+    let mut options = Options::default(true, false, false, false);
+
+    for source in &[",[->++>+<<]", ",[>++>+<<-]", ",[>+>+<+<-]"] {
+        let described = format!("{:?}", Interpreter::interpret(&mut source.bytes(), &options).unwrap());
+        assert!(described.starts_with("Routine([Input, AddAndZero(-1, [(1, 2), (2, 1)], Routine("), "{}", described);
+    }
+
+    options.arithmetic = Arithmetic::Saturate;
+    for &(source, optimized) in &[(",[->++>+<<]", true), (",[>++>+<<-]", false), (",[>+>+<+<-]", false)] {
+        let described = format!("{:?}", Interpreter::interpret(&mut source.bytes(), &options).unwrap());
+        assert_eq!(described.contains("AddAndZero"), optimized, "{}", source);
+    }
+
+    options.arithmetic = Arithmetic::Wrap;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[<+>-]".bytes(), &options).unwrap()),
+        "Routine([Input, AddAndZero(-1, [(-1, 1)], Routine([Seek(-1), Inc(1), Seek(1), Inc(-1)], true))], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[->+<<>]".bytes(), &options).unwrap()),
+        "Routine([Input, AddAndZero(-1, [(-1, 0), (1, 1)], Routine([Inc(-1), Seek(1), Inc(1), Seek(-2), Seek(1)], true))], false)",
+    );
+
+    // Loops with other operations, or moving the pointer, are kept
+    for source in &[",[->+<.]", ",[->+<<]"] {
+        let described = format!("{:?}", Interpreter::interpret(&mut source.bytes(), &options).unwrap());
+        assert!(!described.contains("AddAndZero"), "{}", described);
+    }
+}
+
+/// Test whether loops that run at most once are optimized into conditional
+/// routines.
+#[test]
+fn test_if() {
+    let mut options = Options::default(true, false, false, false);
+    options.tape_limit = Some(usize::MAX);
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[>+<[-]],[-[>]],[>[-]<+]".bytes(), &options).unwrap()),
+        "Routine([Input, If([IncAt(1, 1), Zero]), Input, If([Inc(-1), Scan(1)]), Input, Routine([SetAt(1, 0), Inc(1)], true)], false)",
+    );
+}
+
+/// Test whether strict optimization keeps zeroing routines that may loop
+/// forever, and whether routines that trap are kept regardless.
+#[test]
+fn test_strict_zero() {
+    use super::Arithmetic;
+
+    let mut options = Options::default(true, false, false, false);
+    options.opt_level = 1;
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[++],[+++]".bytes(), &options).unwrap()),
+        "Routine([Input, Zero, Input, Zero], false)",
+    );
+
+    // Routines that overflow before reaching zero trap, also without strict
+    // optimization
+    options.arithmetic = Arithmetic::Trap;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[+],[++],[-]".bytes(), &options).unwrap()),
+        "Routine([Input, AddAndZero(1, [], Routine([Inc(1)], true)), Input, AddAndZero(2, [], Routine([Inc(2)], true)), Input, Zero], false)",
+    );
+
+    options.arithmetic = Arithmetic::Wrap;
+    options.strict = true;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[++],[+++]".bytes(), &options).unwrap()),
+        "Routine([Input, AddAndZero(2, [], Routine([Inc(2)], true)), Input, Zero], false)",
+    );
+
+    options.arithmetic = Arithmetic::Saturate;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",[+],[---]".bytes(), &options).unwrap()),
+        "Routine([Input, AddAndZero(1, [], Routine([Inc(1)], true)), Input, Zero], false)",
+    );
+}
+
+/// Test whether runs of adding and seeking are collapsed into blocks, keeping
+/// the original operations and the lowest and highest cell reached.
+#[test]
+fn test_block() {
+    let options = Options::default(true, false, false, false);
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",>+>++<<-.>>-<+>>,[->>+<-<+>]".bytes(), &options).unwrap()),
+        "Routine([Input, Block([(0, -1), (1, 1), (2, 2)], 0, Routine([IncAt(1, 1), IncAt(2, 2), Inc(-1)], false)), Output, Block([(1, 1), (2, -1)], 0, Routine([IncAt(2, -1), IncAt(1, 1)], false)), InputAt(3), Seek(3), Routine([Block([(1, -1), (2, 1)], 1, Routine([Inc(-1), IncAt(2, 1), IncAt(1, -1), Inc(1), Seek(1)], false))], true)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<+>>>>+<<.>>><<+<+.".bytes(), &options).unwrap()),
+        "Routine([Input, Block([(-1, 1), (3, 1)], -1, Routine([Seek(-1), Inc(1), IncAt(4, 1)], false)), OutputAt(2), Block([(2, 1), (3, 1), (5, 0)], 2, Routine([Seek(5), Seek(-2), Inc(1), Seek(-1), Inc(1)], false)), Output], false)",
+    );
+
+    // Runs adding to a single cell are kept as is
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",>+>.<<-.".bytes(), &options).unwrap()),
+        "Routine([Input, IncAt(1, 1), OutputAt(2), Inc(-1), Output], false)",
+    );
+}
+
+/// Test whether nested multiplication loops are optimized into a product.
+#[test]
+fn test_product() {
+    let options = Options::default(true, false, false, false);
+
+    let described = format!("{:?}", Interpreter::interpret(&mut ",>,<[->[->+>+<<]>>[-<<+>>]<<<]".bytes(), &options).unwrap());
+    assert!(
+        described.starts_with("Routine([Input, InputAt(1), Product(-1, [(1, 0, [(3, 1)]), (2, 0, [(1, 1)]), (3, 0, [(3, -1)])], [(2, 0, [(1, 1)])], Routine("),
+        "{}",
+        described,
+    );
+
+    // Loops moving the pointer past the cells they add to are kept
+    let described = format!("{:?}", Interpreter::interpret(&mut ",>,<[->[->+>+<<]>>[-<<+>>]<<<<>]".bytes(), &options).unwrap());
+    assert!(!described.contains("Product"), "{}", described);
+}
+
+/// Test whether known division and modulo routines are optimized, also when
+/// mirrored.
+#[test]
+fn test_div_mod() {
+    let mut options = Options::default(true, false, false, false);
+    options.tape_limit = Some(usize::MAX);
+
+    let cases = [
+        (",>,<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]", "Routine([Input, InputAt(1), DivMod(1, [], Routine([Inc(-1), Seek(1), "),
+        (",>>,<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]", "Routine([Input, InputAt(2), DivMod(2, [(1, 1)], Routine([Inc(-1), Seek(1), "),
+        (">>>>>,>,[-<-[<+<<]<[+[->+<]<+<<]>>>>>]", "Routine([InputAt(5), InputAt(6), Seek(6), DivMod(-1, [], Routine([Inc(-1), Seek(-1), "),
+    ];
+    for &(source, expected) in &cases {
+        let described = format!("{:?}", Interpreter::interpret(&mut source.bytes(), &options).unwrap());
+        assert!(described.starts_with(expected), "{}", described);
+    }
+}
+
+/// Test whether operations without effect are removed, and neighbours are
+/// fused again.
+#[test]
+fn test_canonicalize() {
+    use super::Boundary;
+
+    let mut options = Options::default(true, false, false, false);
+    options.tape_limit = Some(usize::MAX);
+    options.opt_level = 1;
+    options.fresh_memory = true;
+
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",><+-.+++[-]+-.[-]++>[-]<<,".bytes(), &options).unwrap()),
+        "Routine([Input, Output, Zero, Output, Set(2), Seek(1), Zero, Seek(-2), Input], false)",
+    );
+
+    options.opt_level = 2;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut "+>[-]<+.".bytes(), &options).unwrap()),
+        "Routine([Inc(2), Output], false)",
+    );
+
+    // Seeks and zeroing past an end of the tape that errors are kept
+    options.tape_left = Boundary::Error;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<>.".bytes(), &options).unwrap()),
+        "Routine([Input, Seek(-1), OutputAt(1), Seek(1)], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<[]>.".bytes(), &options).unwrap()),
+        "Routine([Input, SetAt(-1, 0), Output], false)",
+    );
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<-+>.".bytes(), &options).unwrap()),
+        "Routine([Input, IncAt(-1, 0), Output], false)",
+    );
+
+    // Seeks growing the tape in front of an end that wraps are kept
+    options.tape_size = 1;
+    options.tape_left = Boundary::Wrap;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",>+-<.".bytes(), &options).unwrap()),
+        "Routine([Input, IncAt(1, 0), Output], false)",
+    );
+    options.opt_level = 1;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",>+-<.".bytes(), &options).unwrap()),
+        "Routine([Input, Seek(1), Seek(-1), Output], false)",
+    );
+    options.tape_left = Boundary::Grow;
+    options.tape_right = Boundary::Wrap;
+    assert_eq!(
+        format!("{:?}", Interpreter::interpret(&mut ",<+->.".bytes(), &options).unwrap()),
+        "Routine([Input, Seek(-1), Seek(1), Output], false)",
+    );
+}
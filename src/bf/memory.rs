@@ -1,5 +1,9 @@
-/// The size of the memory.
-const MEM_SIZE: usize = 30_000;
+use super::{Arithmetic, Boundary, Cell, Options, RunError, Sum};
+
+
+
+/// The default size of the memory.
+pub const MEM_SIZE: usize = 30_000;
 
 
 
@@ -7,118 +11,922 @@ const MEM_SIZE: usize = 30_000;
 ///
 /// This struct defines the state of such a program,
 /// and provides helper functions to easily manage it.
-pub struct Memory {
-    /// The memory data set
-    data: [u8; MEM_SIZE],
+///
+/// The memory is generic over its cell type, which defaults to 8-bit cells.
+#[derive(Clone)]
+pub struct Memory<C: Cell = u8> {
+    /// The memory data set, including spare capacity to grow into
+    data: Vec<C>,
+
+    /// Index of the first cell in the data set that is on the tape
+    start: usize,
+
+    /// Index right after the last cell in the data set that is on the tape
+    end: usize,
+
+    /// Index of the cell in the data set the pointer started at.
+    /// This moves when the memory grows at the left.
+    origin: usize,
+
+    /// The number of cells the tape was created with
+    size: usize,
+
+    /// The number of cells the tape may grow to
+    limit: usize,
+
+    /// What happens when the pointer moves past the left end
+    left: Boundary,
+
+    /// What happens when the pointer moves past the right end
+    right: Boundary,
 
     /// Index of the current memory cell pointer
     pointer: usize,
 }
 
 impl Memory {
-    /// Create new application memory.
+    /// Create new application memory with 8-bit cells, with the default size.
     ///
     /// This allocates all memory the program might use,
     /// and returns the initial memory state.
     pub fn new() -> Memory {
+        Memory::with_size(MEM_SIZE, None)
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl<C: Cell> Memory<C> {
+    /// Create new application memory with `size` cells.
+    ///
+    /// If a `limit` is given, the memory grows on demand when the pointer
+    /// moves past the right end, up to `limit` cells. The pointer is clamped
+    /// at the left end.
+    pub fn with_size(size: usize, limit: Option<usize>) -> Memory<C> {
+        Memory::with_tape(size, limit, Boundary::Clamp, Boundary::Grow)
+    }
+
+    /// Create new application memory with `size` cells, and the given
+    /// boundaries at the `left` and `right` end.
+    ///
+    /// Memory may grow at a growing boundary up to `limit` cells in total.
+    /// Without a limit, memory doesn't grow past `size`.
+    pub fn with_tape(
+        size: usize,
+        limit: Option<usize>,
+        left: Boundary,
+        right: Boundary,
+    ) -> Memory<C> {
+        let size = size.max(1);
         Memory {
-            data: [0; MEM_SIZE],
+            data: vec![C::default(); size],
+            start: 0,
+            end: size,
+            origin: 0,
+            size,
+            limit: limit.unwrap_or(size).max(size),
+            left,
+            right,
             pointer: 0,
         }
     }
 
+    /// Create new application memory, sized as configured in `options`.
+    pub fn from_options(options: &Options) -> Memory<C> {
+        Memory::with_tape(
+            options.tape_size,
+            options.tape_limit,
+            options.tape_left,
+            options.tape_right,
+        )
+    }
+
+    /// Check whether this memory was created as configured in `options`, with
+    /// cells of the configured width. See `from_options`.
+    ///
+    /// Only the configuration is checked, not the state of the cells.
+    pub fn matches(&self, options: &Options) -> bool {
+        let size = options.tape_size.max(1);
+        self.size == size
+            && self.limit == options.tape_limit.unwrap_or(size).max(size)
+            && self.left == options.tape_left
+            && self.right == options.tape_right
+            && C::BITS == options.cell_width.bits()
+    }
+
     /// Seek the memory cell pointer for the given relative `amount`.
     ///
-    /// The pointer won't underflow as specified by the brainfuck
-    /// specification. Instead the pointer would be set to zero.
-    pub fn seek(&mut self, amount: isize) {
-        self.pointer = Memory::seek_virtual(self.pointer, amount);
+    /// Moving past an end of the memory is handled as specified by the
+    /// boundary at that end.
+    ///
+    /// An error is returned if the pointer moves out of bounds.
+    pub fn seek(&mut self, amount: isize) -> Result<(), RunError> {
+        let pointer = self.pointer as isize + amount;
+        self.pointer = self.reach(pointer)?;
+        Ok(())
+    }
+
+    /// Make sure the memory cell at data set `index` is on the tape, and
+    /// return its actual index.
+    ///
+    /// If the index is past an end of the tape, the boundary at that end
+    /// defines the cell that is reached. Growing memory at the left may shift
+    /// all indices.
+    ///
+    /// An error is returned if the index is out of bounds.
+    fn reach(&mut self, index: isize) -> Result<usize, RunError> {
+        let (start, end) = (self.start as isize, self.end as isize);
+        if index >= start && index < end {
+            return Ok(index as usize);
+        }
+
+        match if index < start { self.left } else { self.right } {
+            Boundary::Clamp if index < start => Ok(self.start),
+            Boundary::Clamp => Ok(self.end - 1),
+            Boundary::Wrap => Ok((start + (index - start).rem_euclid(end - start)) as usize),
+            Boundary::Error => Err(self.out_of_bounds(index)),
+            Boundary::Grow => self.grow(index),
+        }
+    }
+
+    /// Make sure the memory cell at the relative `offset` from the pointer
+    /// is on the tape, and return its actual index. See `reach`.
+    fn reach_offset(&mut self, offset: isize) -> Result<usize, RunError> {
+        let index = self.pointer as isize + offset;
+        self.reach(index)
+    }
+
+    /// Check whether the memory cell at the relative `offset` from the pointer
+    /// is on the tape, without growing it.
+    fn inside(&self, offset: isize) -> bool {
+        let index = self.pointer as isize + offset;
+        index >= self.start as isize && index < self.end as isize
     }
 
-    /// Seek a virtual memory cell pointer by the given relative `amount`.
-    /// The new pointer position is returned.
+    /// Grow the tape to include the cell at data set `index`, past one of
+    /// the ends. Return the actual index of that cell after growing.
+    ///
+    /// Only the cells up to the reached cell count towards the limit. Spare
+    /// capacity is reserved in the data set to limit reallocation, but isn't
+    /// on the tape.
     ///
-    /// This method follows the brainfuck specificaiton, not allowing the
-    /// index to underflow. If the number would underflow, zero is returned.
-    fn seek_virtual(pointer: usize, amount: isize) -> usize {
-        if amount >= 0 {
-            pointer + (amount as usize)
+    /// An error is returned if the tape can't grow far enough.
+    fn grow(&mut self, index: isize) -> Result<usize, RunError> {
+        // Determine the required tape length, and the data set length to
+        // reserve, being at least twice the current length
+        let current = self.end - self.start;
+        let required = if index < self.start as isize {
+            (self.end as isize - index) as usize
         } else {
-            pointer.saturating_sub(-amount as usize)
+            (index + 1 - self.start as isize) as usize
+        };
+        if required > self.limit {
+            return Err(self.out_of_bounds(index));
+        }
+        let reserve = required.max(current * 2).min(self.limit);
+
+        // Grow at the right, into spare capacity if there is any
+        if index >= self.end as isize {
+            if index as usize >= self.data.len() {
+                self.data.resize(self.start + reserve, C::default());
+            }
+            self.end = index as usize + 1;
+            return Ok(index as usize);
+        }
+
+        // Grow at the left, shift all indices if there is no spare capacity
+        let mut index = index;
+        if index < 0 {
+            let shift = index.unsigned_abs() + reserve - required;
+            self.data.splice(0..0, vec![C::default(); shift]);
+            self.start += shift;
+            self.end += shift;
+            self.origin += shift;
+            self.pointer += shift;
+            index += shift as isize;
         }
+        self.start = index as usize;
+        Ok(self.start)
+    }
+
+    /// Create an out of bounds error for the given data set `index`.
+    ///
+    /// The error reports the first cell past the end the index is beyond,
+    /// relative to the origin. Seeks may be combined into one, and the
+    /// pointer fails as soon as it moves past that end.
+    fn out_of_bounds(&self, index: isize) -> RunError {
+        let (start, end) = (self.start as isize, self.end as isize);
+        let limit = self.limit.min(isize::MAX as usize) as isize;
+        let first = if index < start {
+            match self.left {
+                Boundary::Grow => end.saturating_sub(limit) - 1,
+                _ => start - 1,
+            }
+        } else {
+            match self.right {
+                Boundary::Grow => start.saturating_add(limit),
+                _ => end,
+            }
+        };
+        RunError::OutOfBounds(first - self.origin as isize, None)
     }
 
     /// Increase the value of the current memory cell by the given relative
     /// `amount`.
     ///
-    /// The pointer won't underflow as specified by the brainfuck
-    /// specification. Instead the memory cell would be set to zero.
-    /// Overflowing is allowed.
-    pub fn inc(&mut self, amount: isize) {
-        self.data[self.pointer] = Memory::inc_virtual(
+    /// Overflowing the cell value is handled as specified by `arithmetic`.
+    /// An error is returned if the value overflows while trapping.
+    pub fn inc(&mut self, amount: isize, arithmetic: Arithmetic) -> Result<(), RunError> {
+        self.data[self.pointer] = Self::inc_virtual(
             self.data[self.pointer],
             amount,
-        );
+            arithmetic,
+        )?;
+        Ok(())
+    }
+
+    /// Increase the value of the memory cell at the relative `offset` from
+    /// the pointer by the given relative `amount`, without moving the pointer.
+    ///
+    /// See `inc`. An error is also returned if the cell is out of bounds.
+    pub fn inc_at(&mut self, offset: isize, amount: isize, arithmetic: Arithmetic) -> Result<(), RunError> {
+        let index = self.reach_offset(offset)?;
+        self.data[index] = Self::inc_virtual(self.data[index], amount, arithmetic)?;
+        Ok(())
+    }
+
+    /// Increase the values of the memory cells at the relative offsets from
+    /// the pointer in `deltas` by their relative amounts, and then seek the
+    /// pointer for the relative amount `seek`.
+    ///
+    /// The offsets must be sorted, and include the lowest and highest offset
+    /// the pointer reaches, see `Op::Block`.
+    ///
+    /// `false` is returned if a cell isn't on the tape, leaving the memory
+    /// unchanged. The original operations must then run one by one, as they
+    /// may not reach the same cells past a boundary.
+    ///
+    /// See `inc`.
+    pub fn inc_block(&mut self, deltas: &[(isize, isize)], seek: isize, arithmetic: Arithmetic) -> Result<bool, RunError> {
+        let first = deltas.first().map_or(0, |&(offset, _)| offset);
+        let last = deltas.last().map_or(0, |&(offset, _)| offset);
+        if !self.inside(first.min(seek)) || !self.inside(last.max(seek)) {
+            return Ok(false);
+        }
+
+        let pointer = self.pointer as isize;
+        for &(offset, amount) in deltas {
+            let index = (pointer + offset) as usize;
+            self.data[index] = Self::inc_virtual(self.data[index], amount, arithmetic)?;
+        }
+        self.pointer = (pointer + seek) as usize;
+        Ok(true)
+    }
+
+    /// Set the value of the memory cell at the relative `offset` from the
+    /// pointer to zero, increased by the given relative `amount`.
+    ///
+    /// Overflowing the cell value is handled as specified by `arithmetic`.
+    /// An error is returned if the cell is out of bounds, or if the value
+    /// overflows while trapping.
+    pub fn set_at(&mut self, offset: isize, amount: isize, arithmetic: Arithmetic) -> Result<(), RunError> {
+        let index = self.reach_offset(offset)?;
+        self.data[index] = Self::inc_virtual(C::default(), amount, arithmetic)?;
+        Ok(())
     }
 
     /// Increate a virutal memory cell by the given relative `amount`.
     /// The new memory cell value is returned.
     ///
-    /// This method follows the brainfuck specification, not allowing the
-    /// index to underflow. If the number would underflow, zero is returned.
-    /// Overflowing is allowed.
-    fn inc_virtual(value: u8, amount: isize) -> u8 {
-        if amount >= 0 {
-            value + (amount as u8)
-        } else {
-            value.saturating_sub(-amount as u8)
+    /// Overflowing the cell value is handled as specified by `arithmetic`.
+    /// An error is returned if the value overflows while trapping.
+    fn inc_virtual(value: C, amount: isize, arithmetic: Arithmetic) -> Result<C, RunError> {
+        match arithmetic {
+            Arithmetic::Wrap => Ok(value.wrapping_inc(amount)),
+            Arithmetic::Saturate => Ok(value.saturating_inc(amount)),
+            Arithmetic::Trap => value.checked_inc(amount)
+                .ok_or(RunError::Overflow(None)),
         }
     }
 
     /// Read and return the value of the current memory cell.
-    pub fn read(&self) -> u8 {
+    pub fn read(&self) -> C {
         self.data[self.pointer]
     }
 
     /// Write the given value to the current memory cell.
-    pub fn write(&mut self, value: u8) {
+    pub fn write(&mut self, value: C) {
         self.data[self.pointer] = value;
     }
 
+    /// Read and return the value of the memory cell at the relative `offset`
+    /// from the pointer.
+    ///
+    /// An error is returned if the cell is out of bounds.
+    pub fn read_at(&mut self, offset: isize) -> Result<C, RunError> {
+        let index = self.reach_offset(offset)?;
+        Ok(self.data[index])
+    }
+
+    /// Write the given value to the memory cell at the relative `offset` from
+    /// the pointer.
+    ///
+    /// An error is returned if the cell is out of bounds.
+    pub fn write_at(&mut self, offset: isize, value: C) -> Result<(), RunError> {
+        let index = self.reach_offset(offset)?;
+        self.data[index] = value;
+        Ok(())
+    }
+
+    /// Write the given values to the consecutive memory cells, starting at the
+    /// relative `offset` from the pointer. Values are truncated to the cell
+    /// width.
+    ///
+    /// An error is returned if a cell is out of bounds.
+    pub fn load(&mut self, offset: isize, values: &[u64]) -> Result<(), RunError> {
+        for (index, &value) in values.iter().enumerate() {
+            self.write_at(offset + index as isize, C::from_u64(value))?;
+        }
+        Ok(())
+    }
+
+    /// Get the index of the current memory cell pointer in `cells`.
+    pub fn pointer(&self) -> usize {
+        self.pointer - self.start
+    }
+
+    /// Get the index in `cells` of the cell the pointer started at.
+    ///
+    /// This is zero, unless the memory has grown at the left end.
+    pub fn origin(&self) -> usize {
+        self.origin - self.start
+    }
+
+    /// Get all memory cells on the tape.
+    pub fn cells(&self) -> &[C] {
+        &self.data[self.start..self.end]
+    }
+
     /// Check whether the current memory cell is zero.
     pub fn zero(&self) -> bool {
-        self.data[self.pointer] == 0
+        self.data[self.pointer] == C::default()
     }
 
     /// Set the current memory cell value to zero.
     pub fn set_zero(&mut self) {
-        self.data[self.pointer] = 0;
+        self.data[self.pointer] = C::default();
+    }
+
+    /// Seek the memory cell pointer in steps of `stride`, until the current
+    /// memory cell is zero.
+    ///
+    /// Cells are searched within the memory at once. Moving past an end of
+    /// the memory is handled as specified by the boundary at that end, after
+    /// which the search continues, like seeking step by step.
+    ///
+    /// `false` is returned if the pointer is clamped or wraps at the end, and
+    /// never reaches a zero cell. The pointer is then left at a cell the
+    /// search passed, and seeking must continue step by step, like the
+    /// original loop.
+    ///
+    /// An error is returned if the pointer moves out of bounds.
+    pub fn scan(&mut self, stride: isize) -> Result<bool, RunError> {
+        let step = stride.unsigned_abs();
+        let mut passes = 0;
+        while !self.zero() {
+            // Search the cells up to the end of the tape, in the direction of
+            // the stride
+            let zero = |cell: &C| *cell == C::default();
+            let (start, end) = (self.start, self.end);
+            let found = match stride {
+                1 => self.data[self.pointer..end].iter().position(zero),
+                -1 => self.data[start..=self.pointer].iter().rev().position(zero),
+                _ if stride > 0 => self.data[self.pointer..end].iter().step_by(step).position(zero),
+                _ => self.data[start..=self.pointer].iter().rev().step_by(step).position(zero),
+            };
+
+            // Move to the found cell, or to the last cell on the tape, and
+            // seek past the end
+            if stride > 0 {
+                let last = (end - 1 - self.pointer) / step;
+                self.pointer += found.unwrap_or(last) * step;
+            } else {
+                let last = (self.pointer - start) / step;
+                self.pointer -= found.unwrap_or(last) * step;
+            }
+            if found.is_none() {
+                // Past a clamping or wrapping end, the search continues at
+                // one of the first `step` cells from that end. If more passes
+                // than that find no zero, one of these cells repeats
+                passes += 1;
+                if passes > step + 1 {
+                    return Ok(false);
+                }
+                self.seek(stride)?;
+            }
+        }
+
+        Ok(true)
     }
 
-    /// Move the current cell value to the given relative targets,
-    /// zeroing the current cell.
+    /// Loop the current cell value to zero, adding to the given relative
+    /// targets in each iteration.
+    ///
+    /// Each iteration changes the current cell by `step`, and adds the amount
+    /// given along with each target to the cell at that relative position.
+    /// The number of iterations is determined exactly for the cell width and
+    /// `arithmetic`, as if the loop ran step by step.
     ///
-    /// The targets 
-    pub fn copy_zero(&mut self, targets: &Vec<(isize, f32)>) {
+    /// The targets must be sorted, see `Op::AddAndZero`.
+    ///
+    /// `false` is returned if the loop never reaches zero, or if a target
+    /// isn't on the tape, leaving the memory unchanged. The loop must then run
+    /// step by step, like the original loop.
+    ///
+    /// An error is returned if a value overflows while trapping.
+    pub fn copy_zero(
+        &mut self,
+        step: isize,
+        targets: &[(isize, isize)],
+        arithmetic: Arithmetic,
+    ) -> Result<bool, RunError> {
         // Read the cell value, return if it is zero
         let value = self.data[self.pointer];
-        if value == 0 {
-            return;
+        if value == C::default() {
+            return Ok(true);
+        }
+
+        // All targets must be on the tape, as the loop may not reach the
+        // same cells past a boundary
+        let first = targets.first().map_or(0, |&(target, _)| target);
+        let last = targets.last().map_or(0, |&(target, _)| target);
+        if !self.inside(first) || !self.inside(last) {
+            return Ok(false);
         }
 
+        // Determine the number of iterations
+        let count = match Self::trip_count(value, step, arithmetic)? {
+            Some(count) => count,
+            None => return Ok(false),
+        };
+
         // Write the values
-        for &(target, factor) in targets {
+        for &(target, amount) in targets {
             // Determine the pointer position
-            let pointer = Memory::seek_virtual(self.pointer, target);
+            let pointer = (self.pointer as isize + target) as usize;
 
-            // Increase the data in the cell
-            self.data[pointer] = Memory::inc_virtual(
+            // Increase the data in the cell for all iterations at once
+            self.data[pointer] = Self::inc_times(
                 self.data[pointer],
-                (value as f32 * factor) as isize,
-            );
+                amount,
+                count,
+                arithmetic,
+            )?;
         }
 
         // Zero the current cell
         self.set_zero();
+
+        Ok(true)
     }
+
+    /// Loop the current cell value to zero, adding sums of multiples of
+    /// relative cells to the given relative targets in each iteration.
+    ///
+    /// Each iteration changes the current cell by `step`. The first iteration
+    /// adds the sums in `first` to their targets, all other iterations add
+    /// the sums in `rest`, see `Op::Product`. Cell values wrap.
+    ///
+    /// `false` is returned if the loop never reaches zero, or if a cell used
+    /// by `first` isn't on the tape, leaving the memory unchanged. The loop
+    /// must then run step by step, like the original loop.
+    pub fn product(
+        &mut self,
+        step: isize,
+        first: &[Sum],
+        rest: &[Sum],
+    ) -> Result<bool, RunError> {
+        // Read the cell value, return if it is zero
+        let value = self.data[self.pointer];
+        if value == C::default() {
+            return Ok(true);
+        }
+
+        // All cells must be on the tape, as the loop may not reach the same
+        // cells past a boundary
+        let inside = first.iter().all(|&(target, _, ref terms)| {
+            self.inside(target) && terms.iter().all(|&(offset, _)| self.inside(offset))
+        });
+        if !inside {
+            return Ok(false);
+        }
+
+        // Determine the number of iterations
+        let count = match wrapping_trip_count(value.to_u64(), step, C::BITS) {
+            Some(count) => count,
+            None => return Ok(false),
+        };
+
+        // Run the first iteration, and all others at once
+        self.add_sums(step, first, 1)?;
+        self.add_sums(step, rest, count - 1)?;
+        Ok(true)
+    }
+
+    /// Loop the current cell value to zero in steps of one, dividing the
+    /// number of iterations by the divisor at the relative offset `divisor`,
+    /// and adding to the given relative targets in each iteration.
+    ///
+    /// The cells following the divisor in the direction of its offset hold
+    /// the remainder and the quotient, see `Op::DivMod`. All results are
+    /// determined at once.
+    ///
+    /// `false` is returned if the results can't be determined at once,
+    /// leaving the memory unchanged. This is the case if `arithmetic` doesn't
+    /// wrap, if a cell is outside the memory, if the two cells after the
+    /// quotient aren't zero, or if the divisor is one. The routine must then
+    /// run step by step, like the original routine.
+    pub fn div_mod(
+        &mut self,
+        divisor: isize,
+        targets: &[(isize, isize)],
+        arithmetic: Arithmetic,
+    ) -> Result<bool, RunError> {
+        // Read the cell value, return if it is zero
+        let count = self.data[self.pointer].to_u64();
+        if count == 0 {
+            return Ok(true);
+        }
+
+        // All cells must be on the tape, as the routine may not reach the
+        // same cells past a boundary
+        let step = divisor.signum();
+        if arithmetic != Arithmetic::Wrap
+            || !self.inside(divisor)
+            || !self.inside(divisor + 4 * step)
+            || !targets.iter().all(|&(target, _)| self.inside(target))
+        {
+            return Ok(false);
+        }
+
+        // The cells after the quotient must be zero
+        if self.read_at(divisor + 3 * step)? != C::default() || self.read_at(divisor + 4 * step)? != C::default() {
+            return Ok(false);
+        }
+
+        let modulus = 1u128 << C::BITS;
+        let count = count as u128;
+        let left = self.read_at(divisor)?.to_u64() as u128;
+        let remainder = self.read_at(divisor + step)?.to_u64() as u128;
+        let quotient = self.read_at(divisor + 2 * step)?.to_u64() as u128;
+
+        // The divisor cell counts down, and resets to the total of both
+        // cells when it reaches zero
+        let total = (left + remainder) % modulus;
+        let first = if left == 0 { modulus } else { left };
+        let period = if total == 0 { modulus } else { total };
+        let (left, remainder, quotient) = if count < first {
+            ((left + modulus - count) % modulus, remainder + count, quotient)
+        } else if total != 1 {
+            let rest = count - first;
+            let at = rest % period;
+            ((total + modulus - at) % modulus, at, quotient + 1 + rest / period)
+        } else {
+            return Ok(false);
+        };
+
+        for &(offset, value) in &[(divisor, left), (divisor + step, remainder), (divisor + 2 * step, quotient)] {
+            self.write_at(offset, C::from_u64(value as u64))?;
+        }
+        for &(target, amount) in targets {
+            let pointer = self.reach_offset(target)?;
+            self.data[pointer] = Self::inc_times(self.data[pointer], amount, count as u64, arithmetic)?;
+        }
+        self.set_zero();
+        Ok(true)
+    }
+
+    /// Add the sums of multiples of relative cells in `sums` to their relative
+    /// targets `times` times, and change the current cell by `step` as many
+    /// times. All sums are determined before adding any of them.
+    ///
+    /// Cell values wrap. An error is returned if a cell is past the end of
+    /// the memory, and the memory can't grow.
+    pub(crate) fn add_sums(&mut self, step: isize, sums: &[Sum], times: u64) -> Result<(), RunError> {
+        let mut totals = Vec::with_capacity(sums.len());
+        for &(_, constant, ref terms) in sums {
+            let mut total = constant as u64;
+            for &(offset, factor) in terms {
+                total = total.wrapping_add((factor as u64).wrapping_mul(self.read_at(offset)?.to_u64()));
+            }
+            totals.push(total.wrapping_mul(times));
+        }
+
+        for (&(target, _, _), total) in sums.iter().zip(totals) {
+            let pointer = self.reach_offset(target)?;
+            self.data[pointer] = C::from_u64(self.data[pointer].to_u64().wrapping_add(total));
+        }
+        let total = (step as u64).wrapping_mul(times);
+        self.data[self.pointer] = C::from_u64(self.data[self.pointer].to_u64().wrapping_add(total));
+        Ok(())
+    }
+
+    /// Determine the number of iterations a loop takes to bring a cell from
+    /// `value` to zero, changing it by `step` in each iteration.
+    ///
+    /// `None` is returned if the cell never reaches zero. An error is returned
+    /// if the cell overflows before reaching zero while trapping.
+    fn trip_count(value: C, step: isize, arithmetic: Arithmetic) -> Result<Option<u64>, RunError> {
+        let value = value.to_u64();
+        let magnitude = step.unsigned_abs() as u64;
+        match arithmetic {
+            Arithmetic::Wrap => Ok(wrapping_trip_count(value, step, C::BITS)),
+            Arithmetic::Saturate if step < 0 => Ok(Some(value.div_ceil(magnitude))),
+            Arithmetic::Saturate => Ok(None),
+            Arithmetic::Trap if step < 0 && value.is_multiple_of(magnitude) => Ok(Some(value / magnitude)),
+            Arithmetic::Trap => Err(RunError::Overflow(None)),
+        }
+    }
+
+    /// Increase a virtual memory cell by the given relative `amount`, `count`
+    /// times. The new memory cell value is returned.
+    ///
+    /// The result is the same as increasing the cell `count` times in a row.
+    /// An error is returned if the value overflows while trapping.
+    fn inc_times(value: C, amount: isize, count: u64, arithmetic: Arithmetic) -> Result<C, RunError> {
+        // Wrapping only depends on the low bits
+        if arithmetic == Arithmetic::Wrap {
+            let total = count.wrapping_mul(amount as u64);
+            return Ok(C::from_u64(value.to_u64().wrapping_add(total)));
+        }
+
+        // The amount is constant, so the value overflows if the result does
+        let total = value.to_u64() as i128 + count as i128 * amount as i128;
+        if total >= 0 && total <= C::MAX.to_u64() as i128 {
+            Ok(C::from_u64(total as u64))
+        } else if arithmetic == Arithmetic::Trap {
+            Err(RunError::Overflow(None))
+        } else if total < 0 {
+            Ok(C::default())
+        } else {
+            Ok(C::MAX)
+        }
+    }
+}
+
+/// Determine the number of iterations a loop takes to bring a wrapping cell of
+/// `bits` wide from `value` to zero, changing it by `step` in each iteration.
+///
+/// This solves `value + count * step = 0` modulo `2^bits` for the smallest
+/// `count`, using the modular inverse of the odd part of `step`.
+/// `None` is returned if there is no solution, and the loop never ends.
+fn wrapping_trip_count(value: u64, step: isize, bits: u32) -> Option<u64> {
+    let mask = if bits >= 64 { u64::MAX } else { (1 << bits) - 1 };
+
+    // Split the step into a power of two and an odd part, the value must be
+    // divisible by the same power of two
+    let step = step as u64 & mask;
+    if step == 0 {
+        return None;
+    }
+    let shift = step.trailing_zeros();
+    if value & ((1 << shift) - 1) != 0 {
+        return None;
+    }
+
+    // Solve for the odd part, modulo the remaining bits
+    let count = (value.wrapping_neg() >> shift).wrapping_mul(inverse(step >> shift));
+    Some(count & (mask >> shift))
+}
+
+/// Get the multiplicative inverse of the odd number `odd`, modulo `2^64`.
+///
+/// This also is the inverse modulo any smaller power of two.
+fn inverse(odd: u64) -> u64 {
+    // Newton's method, each iteration doubles the number of correct bits,
+    // starting with three
+    let mut inverse = odd;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(odd.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+
+/// Test whether the memory grows on demand up to its limit.
+#[test]
+fn test_grow() {
+    let mut memory: Memory = Memory::with_size(2, Some(5));
+    memory.seek(3).unwrap();
+    memory.inc(1, Arithmetic::Wrap).unwrap();
+    assert_eq!(memory.cells(), &[0, 0, 0, 1]);
+
+    memory.seek(1).unwrap();
+    assert_eq!(memory.cells().len(), 5);
+    match memory.seek(1) {
+        Err(RunError::OutOfBounds(5, None)) => {},
+        _ => panic!("memory grew past its limit"),
+    }
+
+    // Only cells that were reached count towards the limit, whatever the path
+    for &path in &[&[-1, 1, 1, 1][..], &[-1, -1, 1, 1, 1]] {
+        let mut memory: Memory = Memory::with_tape(1, Some(4), Boundary::Grow, Boundary::Grow);
+        for &amount in path {
+            memory.seek(amount).unwrap();
+        }
+        assert!(memory.cells().len() <= 4);
+    }
+
+    // Wrapping at the other end only wraps around the reached cells
+    let mut memory: Memory = Memory::with_tape(2, Some(8), Boundary::Wrap, Boundary::Grow);
+    memory.seek(2).unwrap();
+    memory.seek(-3).unwrap();
+    assert_eq!((memory.pointer(), memory.cells().len()), (2, 3));
+}
+
+/// Test whether cell overflows follow the selected arithmetic.
+#[test]
+fn test_arithmetic() {
+    let mut memory = Memory::new();
+    memory.inc(-1, Arithmetic::Wrap).unwrap();
+    assert_eq!(memory.read(), 255);
+    memory.inc(300, Arithmetic::Saturate).unwrap();
+    assert_eq!(memory.read(), 255);
+    assert!(memory.inc(1, Arithmetic::Trap).is_err());
+    memory.inc(-255, Arithmetic::Trap).unwrap();
+    assert!(memory.zero());
+
+    let mut memory: Memory<u16> = Memory::with_size(1, None);
+    memory.inc(-1, Arithmetic::Wrap).unwrap();
+    assert_eq!(memory.read(), u16::MAX);
+}
+
+/// Test whether the pointer follows the boundaries at both ends.
+#[test]
+fn test_boundaries() {
+    let mut memory: Memory = Memory::with_tape(3, None, Boundary::Wrap, Boundary::Clamp);
+    memory.seek(-1).unwrap();
+    assert_eq!(memory.pointer(), 2);
+    memory.seek(5).unwrap();
+    assert_eq!(memory.pointer(), 2);
+
+    let mut memory: Memory = Memory::with_tape(2, None, Boundary::Error, Boundary::Clamp);
+    match memory.seek(-2) {
+        Err(RunError::OutOfBounds(-1, None)) => {},
+        _ => panic!("pointer didn't error at the left end"),
+    }
+
+    let mut memory: Memory = Memory::with_tape(2, Some(8), Boundary::Grow, Boundary::Grow);
+    memory.seek(1).unwrap();
+    memory.inc(1, Arithmetic::Wrap).unwrap();
+    memory.seek(-3).unwrap();
+    memory.inc(2, Arithmetic::Wrap).unwrap();
+    assert_eq!(memory.cells()[memory.pointer()..], [2, 0, 0, 1]);
+    assert_eq!(memory.pointer() + 2, memory.origin());
+    memory.seek(7).unwrap();
+    match memory.seek(1) {
+        Err(RunError::OutOfBounds(6, None)) => {},
+        _ => panic!("memory grew past its limit"),
+    }
+}
+
+/// Test whether copy and multiplication loops match running them step by step.
+#[test]
+fn test_copy_zero() {
+    for value in 0..=255u8 {
+        for &step in &[-1, 1, -3, 5, 2, -4, 6] {
+            // Run the loop step by step, skip it if it never ends
+            let (mut base, mut target, mut iterations) = (value, 0u8, 0);
+            while base != 0 && iterations <= 256 {
+                base = base.wrapping_add(step as u8);
+                target = target.wrapping_add(7);
+                iterations += 1;
+            }
+            if base != 0 {
+                continue;
+            }
+
+            let mut memory: Memory = Memory::with_size(2, None);
+            memory.write(value);
+            assert!(memory.copy_zero(step, &[(1, 7)], Arithmetic::Wrap).unwrap());
+            assert!(memory.zero());
+            assert_eq!(memory.cells()[1], target);
+        }
+    }
+
+    let mut memory: Memory<u64> = Memory::with_size(2, None);
+    memory.write(u64::MAX);
+    memory.copy_zero(-3, &[(1, 2)], Arithmetic::Wrap).unwrap();
+    assert_eq!(memory.cells()[1], (u64::MAX / 3).wrapping_mul(2));
+
+    let mut memory: Memory = Memory::with_size(2, None);
+    memory.write(1);
+    assert!(!memory.copy_zero(-2, &[(1, 1)], Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.cells(), &[1, 0]);
+    assert!(!memory.copy_zero(-1, &[(-1, 1), (1, 1)], Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.cells(), &[1, 0]);
+
+    let mut memory: Memory = Memory::with_size(2, None);
+    memory.write(10);
+    memory.copy_zero(-3, &[(1, 100)], Arithmetic::Saturate).unwrap();
+    assert_eq!(memory.cells(), &[0, 255]);
+    memory.write(10);
+    assert!(memory.copy_zero(-3, &[], Arithmetic::Trap).is_err());
+}
+
+/// Test whether blocks increase cells on the tape at once, and refuse to
+/// handle cells that aren't.
+#[test]
+fn test_inc_block() {
+    let mut memory: Memory = Memory::with_tape(4, Some(8), Boundary::Clamp, Boundary::Grow);
+    assert!(memory.inc_block(&[(0, 1), (2, 3)], 3, Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.pointer(), 3);
+    assert!(memory.inc_block(&[(-3, 1), (-1, -1)], -1, Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.pointer(), 2);
+    assert_eq!(memory.cells(), &[2, 0, 2, 0]);
+
+    assert!(!memory.inc_block(&[(-3, 1)], 0, Arithmetic::Wrap).unwrap());
+    assert!(!memory.inc_block(&[(0, 1)], 2, Arithmetic::Wrap).unwrap());
+    assert_eq!((memory.pointer(), memory.cells()), (2, &[2, 0, 2, 0][..]));
+}
+
+/// Test whether products run the first iteration, and all others at once.
+#[test]
+fn test_product() {
+    let first = [(1, 0, vec![(3, 1)]), (2, 0, vec![(1, 1)]), (3, 0, vec![(3, -1)])];
+    let rest = [(2, 0, vec![(1, 1)])];
+    for &(cells, result) in &[([7, 20, 0, 5], [0, 25, 170, 0]), ([200, 3, 0, 0], [0, 3, 88, 0])] {
+        let mut memory: Memory = Memory::with_size(4, None);
+        for (offset, &value) in cells.iter().enumerate() {
+            memory.write_at(offset as isize, value).unwrap();
+        }
+        assert!(memory.product(-1, &first, &rest).unwrap());
+        assert_eq!(memory.cells(), &result);
+    }
+
+    let mut memory: Memory = Memory::with_size(4, None);
+    memory.load(0, &[3, 1, 0, 0]).unwrap();
+    assert!(!memory.product(-2, &first, &rest).unwrap());
+    assert_eq!(memory.cells(), &[3, 1, 0, 0]);
+    assert!(!memory.product(-1, &[(1, 0, vec![(4, 1)])], &[]).unwrap());
+    assert_eq!(memory.cells(), &[3, 1, 0, 0]);
+}
+
+/// Test whether division and modulo routines are determined at once, unless
+/// the cells aren't laid out as expected.
+#[test]
+fn test_div_mod() {
+    let mut memory: Memory = Memory::with_size(6, None);
+    memory.load(0, &[200, 7, 0, 0, 0, 0]).unwrap();
+    assert!(memory.div_mod(1, &[], Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.cells(), &[0, 3, 4, 28, 0, 0]);
+
+    memory.load(0, &[9, 3, 0, 0, 1, 0]).unwrap();
+    assert!(!memory.div_mod(1, &[], Arithmetic::Wrap).unwrap());
+    memory.write_at(4, 0).unwrap();
+    assert!(!memory.div_mod(1, &[], Arithmetic::Saturate).unwrap());
+    assert!(!memory.div_mod(2, &[], Arithmetic::Wrap).unwrap());
+    assert_eq!(memory.cells(), &[9, 3, 0, 0, 0, 0]);
+}
+
+/// Test whether scanning for a zero cell follows the boundaries.
+#[test]
+fn test_scan() {
+    // Fill the memory as `[1, 1, 1, 0, 1, 1, 1, 0]`
+    let mut memory: Memory = Memory::with_tape(8, None, Boundary::Error, Boundary::Wrap);
+    for &amount in &[0, 1, 1, 2, 1, 1] {
+        memory.seek(amount).unwrap();
+        memory.write(1);
+    }
+
+    memory.seek(-6).unwrap();
+    assert!(memory.scan(1).unwrap());
+    assert_eq!(memory.pointer(), 3);
+    memory.seek(1).unwrap();
+    assert!(memory.scan(3).unwrap());
+    assert_eq!(memory.pointer(), 7);
+    memory.seek(-2).unwrap();
+    assert!(memory.scan(3).unwrap());
+    assert_eq!(memory.pointer(), 3);
+    memory.seek(-1).unwrap();
+    match memory.scan(-1) {
+        Err(RunError::OutOfBounds(-1, None)) => {},
+        _ => panic!("scan didn't error at the left end"),
+    }
+
+    // Scans never reaching a zero cell past a clamping or wrapping end
+    let mut memory: Memory = Memory::with_tape(5, None, Boundary::Clamp, Boundary::Wrap);
+    memory.load(0, &[1, 1, 0, 1, 1]).unwrap();
+    memory.seek(1).unwrap();
+    assert!(!memory.scan(-1).unwrap());
+    assert_eq!(memory.pointer(), 0);
+    memory.seek(3).unwrap();
+    assert!(!memory.scan(5).unwrap());
+    assert_eq!(memory.pointer(), 3);
+    assert!(memory.scan(3).unwrap());
+    assert_eq!(memory.pointer(), 2);
 }
@@ -1,16 +1,26 @@
 extern crate tty_read;
 
 mod bf;
+mod cell;
+mod error;
+mod exit;
 mod interpreter;
 mod memory;
 mod op;
 mod optimize;
 mod options;
+mod program;
+mod verify;
 
 // Reexport
 pub use self::bf::bf;
+pub use self::cell::{Cell, CellWidth};
+pub use self::error::{BfError, ParseError, Position, RunError, Span};
+pub use self::exit::{Exit, Suspension};
 pub use self::interpreter::Interpreter;
-pub use self::memory::Memory;
-pub use self::options::Options;
-
-use self::op::Op;
+pub use self::memory::{Memory, MEM_SIZE};
+pub use self::op::{Node, Op, Sum};
+pub use self::optimize::{pass, Pass, PassManager, RoutinePass, TreePass, VecPass, MAX_LEVEL, PASSES};
+pub use self::options::{Arithmetic, Boundary, Eof, Options};
+pub use self::program::Program;
+pub use self::verify::{Divergence, DivergenceKind};
@@ -1,7 +1,9 @@
-use super::tty_read::TermReader;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
 
-use super::Memory;
+use super::{Cell, Memory};
 use super::Options;
+use super::{Exit, RunError, Span, Suspension};
 
 
 
@@ -14,7 +16,10 @@ use super::Options;
 ///
 /// Brainfuck programs are translated into these operations,
 /// which will define the program structure in-memory for quick execution.
-#[derive(Debug)]
+///
+/// Operations don't depend on the memory cell width, and may be executed on
+/// memory of any cell type.
+#[derive(Clone, Debug)]
 pub enum Op {
     /// A routine wrapping other operations.
     /// This routine may be simple, or it may be conditional with makes the
@@ -26,7 +31,13 @@ pub enum Op {
     /// conditional/loopable.
     /// - `true` if this routine is contitionally loopable.
     /// - `false` if it isn't.
-    Routine(Vec<Op>, bool),
+    Routine(Vec<Node>, bool),
+
+    /// A conditional routine that runs at most once, because it always ends
+    /// with the current memory cell being zero.
+    ///
+    /// The property defines the set of operations contained by this routine.
+    If(Vec<Node>),
 
     /// Seek the memory pointer for the relative amount.
     Seek(isize),
@@ -41,77 +52,473 @@ pub enum Op {
     /// Output the value of the current memory cell.
     Output,
 
+    /// Set the value of the current memory cell to zero, increased by the
+    /// relative amount.
+    Set(isize),
+
+    /// Set the value of the memory cell at the given relative offset to zero,
+    /// increased by the relative amount, without moving the memory pointer.
+    ///
+    /// The first property defines the offset, the second the amount.
+    SetAt(isize, isize),
+
+    /// Increment the value in the memory cell at the given relative offset
+    /// with the relative amount, without moving the memory pointer.
+    ///
+    /// The first property defines the offset, the second the amount.
+    IncAt(isize, isize),
+
+    /// Put a byte from user input into the memory cell at the given relative
+    /// offset, without moving the memory pointer.
+    InputAt(isize),
+
+    /// Output the value of the memory cell at the given relative offset,
+    /// without moving the memory pointer.
+    OutputAt(isize),
+
     /// Set the value of the current memory cell to zero.
     Zero,
 
-    /// Add the current cell value to the given relative targets,
-    /// zeroing the current cell.
-    AddAndZero(Vec<(isize, f32)>),
+    /// Seek the memory pointer in steps of the given stride, until the current
+    /// memory cell is zero.
+    ///
+    /// If no zero cell is ever reached, this seeks forever, consuming fuel in
+    /// each step.
+    Scan(isize),
+
+    /// Loop until the current cell is zero, changing it by the given step in
+    /// each iteration, and adding to the given relative targets.
+    ///
+    /// The first property defines the amount the current cell changes by in
+    /// each iteration, which isn't zero.
+    /// The second property defines the relative targets, along with the
+    /// amount added to each in each iteration. Targets are sorted, no target
+    /// is at the current cell, and no target is listed twice. The original
+    /// routine doesn't move the pointer past the first or last target.
+    /// The third property defines the original routine. This is run if a
+    /// target isn't on the tape, or if the current cell never reaches zero,
+    /// in which case it loops forever consuming fuel in each iteration.
+    AddAndZero(isize, Vec<(isize, isize)>, Box<Op>),
+
+    /// Load the given values into the memory cells starting at the given
+    /// relative offset, without moving the memory pointer.
+    ///
+    /// The first property defines the offset of the first cell, the second
+    /// the values of the consecutive cells.
+    Load(isize, Vec<u64>),
+
+    /// Output the given bytes.
+    Write(Vec<u8>),
+
+    /// Add to the memory cells at the given relative offsets, and seek the
+    /// memory pointer afterwards.
+    ///
+    /// The first property defines the offsets, along with the amount added to
+    /// each. Offsets are sorted, and listed once. The lowest and highest
+    /// offset the original operations move the pointer to are listed as
+    /// well, possibly adding zero.
+    /// The second property defines the relative amount to seek by.
+    /// The third property defines the original operations, which are run if
+    /// a cell isn't on the tape.
+    Block(Vec<(isize, isize)>, isize, Box<Op>),
+
+    /// Loop until the current cell is zero, changing it by the given step in
+    /// each iteration, and adding sums of multiples of relative cells to the
+    /// given relative targets. Cell values wrap.
+    ///
+    /// The first property defines the amount the current cell changes by in
+    /// each iteration, which isn't zero.
+    /// The second property defines the targets of the first iteration, along
+    /// with the constant and the cells with their factor summed into each.
+    /// These sums use the cell values from before the iteration.
+    /// The third property defines the targets of all other iterations in the
+    /// same way. These sums only use cells that don't change in those
+    /// iterations, so the same amount is added in each.
+    /// No target is at the current cell, and no target is listed twice. The
+    /// original routine doesn't move the pointer past the cells of the first
+    /// iteration.
+    /// The fourth property defines the original routine. This is run if a
+    /// cell isn't on the tape, or if the current cell never reaches zero, in
+    /// which case it loops forever consuming fuel in each iteration.
+    Product(isize, Vec<Sum>, Vec<Sum>, Box<Op>),
+
+    /// Loop until the current cell is zero, decreasing it by one in each
+    /// iteration, while counting the iterations in a divisor cell that resets
+    /// to the divisor each time it reaches zero. Cell values wrap.
+    ///
+    /// This is the division and modulo routine
+    /// `[->-[>+>>]>[+[-<+>]>+>>]<<<<<]`. The cells following the divisor cell
+    /// in the direction of its offset hold the remainder and the quotient,
+    /// and the two cells after that must be zero. If they aren't, the
+    /// original routine runs instead.
+    ///
+    /// The first property defines the relative offset of the divisor cell,
+    /// which isn't zero.
+    /// The second property defines the relative targets, along with the
+    /// amount added to each in each iteration. No target is at the current
+    /// cell or at the cells of the division.
+    /// The third property defines the original routine, which is run if the
+    /// results can't be determined at once.
+    DivMod(isize, Vec<(isize, isize)>, Box<Op>),
 }
 
 impl Op {
+    /// Get the operations contained by this routine.
+    ///
+    /// `None` is returned if this isn't a routine.
+    pub fn body(&self) -> Option<&Vec<Node>> {
+        match *self {
+            Op::Routine(ref ops, _) | Op::If(ref ops) => Some(ops),
+            _ => None,
+        }
+    }
+
+    /// Get the operations contained by this routine, mutably.
+    ///
+    /// `None` is returned if this isn't a routine.
+    pub fn body_mut(&mut self) -> Option<&mut Vec<Node>> {
+        match *self {
+            Op::Routine(ref mut ops, _) | Op::If(ref mut ops) => Some(ops),
+            _ => None,
+        }
+    }
+
     /// Execute the current operation.
     ///
     /// If this operation is a conditional routine, the condition is properly
     /// evaluated as expected.
     ///
-    /// The given `memory` and `output` objects are used to execute these
-    /// operations on, if relevant.
-    pub fn execute(&self, memory: &mut Memory, options: &Options, output: &mut Vec<u8>) {
+    /// The given `memory` is used to execute these operations on.
+    /// Program input is read from `input`, program output is written to
+    /// `output`. Each executed operation consumes one unit of `fuel` if
+    /// limited, execution is suspended when it runs out.
+    pub fn execute<C, R, W>(
+        &self,
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+        fuel: &mut Option<u64>,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        self.resume(&[], memory, options, input, output, fuel)
+    }
+
+    /// Resume executing the current operation at the given `position`.
+    ///
+    /// The position is relative to this operation, see
+    /// `Suspension::position`. If it is empty, the operation is executed from
+    /// the start as normal.
+    pub fn resume<C, R, W>(
+        &self,
+        position: &[usize],
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+        fuel: &mut Option<u64>,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
         // Invoke operation specific logic
         match *self {
             // Seek the memory cell pointer
-            Op::Seek(amount) => memory.seek(amount),
+            Op::Seek(amount) => memory.seek(amount)?,
 
             // Increase the value in the current memory cell
-            Op::Inc(amount) => memory.inc(amount),
-
-            // Invoke a routine
-            Op::Routine(ref ops, cond) => {
-                // If conditional, skip the routine if the current memory cell
-                // value is zero
-                if cond && memory.zero() {
-                    return;
+            Op::Inc(amount) => memory.inc(amount, options.arithmetic)?,
+
+            // Invoke a routine, or a conditional routine that doesn't loop
+            Op::Routine(ref ops, _) | Op::If(ref ops) => {
+                let (cond, looping) = match *self {
+                    Op::Routine(_, cond) => (cond, cond),
+                    _ => (true, false),
+                };
+
+                // Start at the resume position, or from the start if not
+                // resuming. If conditional, skip the routine if the current
+                // memory cell value is zero
+                let (mut start, inner) = match position.split_first() {
+                    Some((&index, inner)) => (index, inner),
+                    None if cond && memory.zero() => return Ok(Exit::Finished),
+                    None => (0, position),
+                };
+
+                // Resume inside the operation that was suspended
+                if !inner.is_empty() {
+                    match ops[start].resume(inner, memory, options, input, output, fuel)? {
+                        Exit::Finished => start += 1,
+                        Exit::OutOfFuel(suspension) =>
+                            return Ok(Exit::OutOfFuel(suspension.within(start))),
+                    }
                 }
 
                 // Keep looping the routine until the end condition is reached
                 loop {
                     // Execute all contained operations
-                    ops.iter().for_each(|op| op.execute(memory, options, output));
+                    for (index, node) in ops.iter().enumerate().skip(start) {
+                        if let Exit::OutOfFuel(suspension) = node.execute(memory, options, input, output, fuel)? {
+                            return Ok(Exit::OutOfFuel(suspension.within(index)));
+                        }
+                    }
 
-                    // End if not conditional, or if the current memory cell
-                    // value is zero
-                    if !cond || memory.zero() {
+                    // End if not looping, or if the current memory cell value
+                    // is zero
+                    if !looping || memory.zero() {
                         break;
                     }
+
+                    // Looping consumes fuel as well, suspend at the condition
+                    if !consume(fuel) {
+                        return Ok(Exit::OutOfFuel(Suspension::at(vec![ops.len()], None)));
+                    }
+                    start = 0;
                 }
             },
 
+            // Seek to the next zero memory cell
+            Op::Scan(stride) => if !memory.scan(stride)? {
+                return spin(fuel, || memory.seek(stride));
+            },
+
             // Set the value of the current memory cell to zero
             Op::Zero => memory.set_zero(),
 
-            // Output the value of the current memory cell
-            Op::Output => {
-                // Read the value, and push it to the output
-                let value = memory.read();
-                output.push(value);
-
-                // If not buffered, print the value immediately
-                if !options.buffer {
-                    print!("{}", value as char);
-                }
-            },
+            // Output the low byte of the current memory cell
+            Op::Output => output.write_all(&[memory.read().to_byte()])?,
 
             // Handle user input
-            Op::Input => memory.write(
-                TermReader::open_stdin(&options.reader_options)
-                    .expect("failed to open user input reader")
-                    .read_byte()
-                    .expect("failed to read user input")
-            ),
-
-            // Add the current cell value to others, and zero
-            Op::AddAndZero(ref targets) => memory.copy_zero(targets),
+            Op::Input => read_input(0, memory, options, input, output)?,
+
+            // Set the value of the current memory cell
+            Op::Set(amount) => memory.set_at(0, amount, options.arithmetic)?,
+
+            // Set the value of a relative memory cell
+            Op::SetAt(offset, amount) => memory.set_at(offset, amount, options.arithmetic)?,
+
+            // Increase the value in a relative memory cell
+            Op::IncAt(offset, amount) => memory.inc_at(offset, amount, options.arithmetic)?,
+
+            // Output the low byte of a relative memory cell
+            Op::OutputAt(offset) => output.write_all(&[memory.read_at(offset)?.to_byte()])?,
+
+            // Handle user input into a relative memory cell
+            Op::InputAt(offset) => read_input(offset, memory, options, input, output)?,
+
+            // Add the current cell value to others, and zero. Run the
+            // original routine instead if that isn't possible, or resume
+            // inside it
+            Op::AddAndZero(step, ref targets, ref routine) =>
+                if !position.is_empty() || !memory.copy_zero(step, targets, options.arithmetic)? {
+                    return routine.resume(position, memory, options, input, output, fuel);
+                },
+
+            // Load values into memory cells
+            Op::Load(offset, ref values) => memory.load(offset, values)?,
+
+            // Output the bytes
+            Op::Write(ref bytes) => output.write_all(bytes)?,
+
+            // Add to relative memory cells, and seek the memory cell pointer.
+            // Run the original operations instead if that isn't possible, or
+            // resume inside them
+            Op::Block(ref deltas, seek, ref ops) =>
+                if !position.is_empty() || !memory.inc_block(deltas, seek, options.arithmetic)? {
+                    return ops.resume(position, memory, options, input, output, fuel);
+                },
+
+            // Add products of the current cell value and others, and zero.
+            // Run the original routine instead if that isn't possible, or
+            // resume inside it
+            Op::Product(step, ref first, ref rest, ref routine) =>
+                if !position.is_empty() || !memory.product(step, first, rest)? {
+                    return routine.resume(position, memory, options, input, output, fuel);
+                },
+
+            // Divide the current cell value, and zero. Run the original
+            // routine instead if that isn't possible, or resume inside it
+            Op::DivMod(divisor, ref targets, ref routine) =>
+                if !position.is_empty() || !memory.div_mod(divisor, targets, options.arithmetic)? {
+                    return routine.resume(position, memory, options, input, output, fuel);
+                },
+        }
+
+        Ok(Exit::Finished)
+    }
+}
+
+/// Read a byte of user input into the memory cell at the relative `offset`.
+///
+/// Output is flushed first to show any prompt. At the end of input, the cell
+/// is set as configured in `options`.
+fn read_input<C, R, W>(
+    offset: isize,
+    memory: &mut Memory<C>,
+    options: &Options,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), RunError>
+    where
+        C: Cell,
+        R: Read,
+        W: Write,
+{
+    output.flush()?;
+
+    let mut byte = [0];
+    let value = match input.read_exact(&mut byte) {
+        Ok(()) => C::from_byte(byte[0]),
+        Err(ref err) if err.kind() == ErrorKind::UnexpectedEof =>
+            options.eof.value(memory.read_at(offset)?),
+        Err(err) => return Err(err.into()),
+    };
+    memory.write_at(offset, value)
+}
+
+/// Consume one unit of `fuel`, if limited.
+/// Return `false` if there is no fuel left.
+pub(crate) fn consume(fuel: &mut Option<u64>) -> bool {
+    match *fuel {
+        Some(0) => false,
+        Some(ref mut left) => {
+            *left -= 1;
+            true
+        },
+        None => true,
+    }
+}
+
+/// Run a loop that never ends, calling `iterate` for each iteration. Each
+/// iteration consumes one unit of `fuel`.
+///
+/// Without a fuel limit this never returns, like the original loop. Otherwise
+/// execution is suspended before the operation running the loop once fuel
+/// runs out. The loop doesn't end from any iteration, so resuming simply runs
+/// that operation again.
+fn spin<F>(fuel: &mut Option<u64>, mut iterate: F) -> Result<Exit, RunError>
+    where
+        F: FnMut() -> Result<(), RunError>,
+{
+    loop {
+        if !consume(fuel) {
+            return Ok(Exit::OutOfFuel(Suspension::at(vec![], None)));
+        }
+        iterate()?;
+    }
+}
+
+/// A sum of multiples of memory cells added to a memory cell, used by
+/// `Op::Product`.
+///
+/// The first element defines the relative offset of the target cell, the
+/// second a constant amount, the third the relative offsets of the summed
+/// cells along with their factor.
+pub type Sum = (isize, isize, Vec<(isize, isize)>);
+
+/// A node in a program operation tree.
+///
+/// This is an operation, along with the range of program source it was
+/// interpreted from.
+#[derive(Clone)]
+pub struct Node {
+    /// The operation.
+    pub op: Op,
+
+    /// The source range the operation was interpreted from.
+    pub span: Span,
+}
+
+impl Node {
+    /// Create a node for the given operation and source range.
+    pub fn new(op: Op, span: Span) -> Node {
+        Node {
+            op,
+            span,
+        }
+    }
+
+    /// Execute the operation of this node, consuming one unit of fuel.
+    ///
+    /// See `Op::execute`. If the operation fails, the source range of this
+    /// node is attached to the error.
+    pub fn execute<C, R, W>(
+        &self,
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+        fuel: &mut Option<u64>,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        self.resume(&[], memory, options, input, output, fuel)
+    }
+
+    /// Resume executing the operation of this node at the given `position`.
+    /// Fuel is consumed if executing from the start.
+    ///
+    /// See `Op::resume`. If the operation fails or is suspended, the source
+    /// range of this node is attached.
+    pub fn resume<C, R, W>(
+        &self,
+        position: &[usize],
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+        fuel: &mut Option<u64>,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        // Consume fuel, suspend before executing if there is none left
+        if position.is_empty() && !consume(fuel) {
+            return Ok(Exit::OutOfFuel(Suspension::at(vec![], Some(self.span))));
         }
+
+        match self.op.resume(position, memory, options, input, output, fuel) {
+            Ok(Exit::OutOfFuel(suspension)) =>
+                Ok(Exit::OutOfFuel(suspension.at_span(self.span))),
+            Ok(Exit::Finished) => Ok(Exit::Finished),
+            Err(err) => Err(err.at(self.span)),
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    /// Describe the operation only, omitting the source range.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.op.fmt(f)
+    }
+}
+
+
+
+/// Test whether reading input at the end of input follows the configured
+/// behaviour.
+#[test]
+fn test_input_eof() {
+    use super::Eof;
+
+    let mut options = Options::default(true, false, false, false);
+    for &(eof, value) in &[(Eof::Unchanged, 3), (Eof::Zero, 0), (Eof::MinusOne, 255)] {
+        options.eof = eof;
+
+        let mut memory = Memory::new();
+        memory.write(3);
+        Op::Input.execute(&mut memory, &options, &mut &b""[..], &mut vec![], &mut None).unwrap();
+        assert_eq!(memory.read(), value);
     }
 }
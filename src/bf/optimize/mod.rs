@@ -1,5 +1,9 @@
+mod pass;
+mod pass_manager;
 mod routine;
-mod routine_optimizer;
+mod tree;
+mod vec;
 
 // Reexport
-pub use self::routine_optimizer::optimize_routine;
+pub use self::pass::{Pass, RoutinePass, TreePass, VecPass};
+pub use self::pass_manager::{pass, PassManager, MAX_LEVEL, PASSES};
@@ -0,0 +1,157 @@
+use super::super::{Node, Op, Options};
+
+
+
+/// An optimization pass over a program operation tree.
+///
+/// Passes are identified by name, and are enabled by default from a minimum
+/// optimization level. See `PassManager`.
+pub trait Pass {
+    /// The name of this pass, used to enable or disable it.
+    fn name(&self) -> &'static str;
+
+    /// The lowest optimization level this pass is enabled at by default.
+    fn level(&self) -> u8;
+
+    /// Run this pass on the operation tree with the given `root`, for a
+    /// program run with the given `options`.
+    ///
+    /// The tree is optimized in place, `true` is returned if it changed.
+    fn run(&self, root: &mut Node, options: &Options) -> bool;
+}
+
+/// A pass replacing whole routines with a single operation.
+///
+/// The optimization is applied on each routine in the tree, innermost first.
+/// It is given whether the routine is conditional, along with the operations
+/// contained by the routine and the options the program is run with.
+/// If `Some` is returned, the whole routine is replaced by it.
+pub struct RoutinePass {
+    /// The name of this pass.
+    pub name: &'static str,
+
+    /// The lowest optimization level this pass is enabled at by default.
+    pub level: u8,
+
+    /// The routine optimization.
+    pub optimize: fn(bool, &[Node], &Options) -> Option<Op>,
+}
+
+impl RoutinePass {
+    /// Optimize the routines in the tree with the given `root`, for a
+    /// program run with the given `options`.
+    /// `true` is returned if the tree changed.
+    fn optimize_tree(&self, root: &mut Node, options: &Options) -> bool {
+        // Optimize the contained routines first
+        let mut changed = false;
+        if let Some(ops) = root.op.body_mut() {
+            for node in ops.iter_mut() {
+                changed |= self.optimize_tree(node, options);
+            }
+        }
+
+        // Replace this routine
+        let replacement = match root.op {
+            Op::Routine(ref ops, cond) => (self.optimize)(cond, ops, options),
+            _ => None,
+        };
+        if let Some(op) = replacement {
+            root.op = op;
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+impl Pass for RoutinePass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn level(&self) -> u8 {
+        self.level
+    }
+
+    fn run(&self, root: &mut Node, options: &Options) -> bool {
+        self.optimize_tree(root, options)
+    }
+}
+
+/// A pass optimizing the vector of operations of each routine.
+///
+/// The optimization is applied on the operations of each routine in the tree,
+/// innermost first, for a program run with the given options. It optimizes
+/// the operations in place, and returns `true` if they changed.
+pub struct VecPass {
+    /// The name of this pass.
+    pub name: &'static str,
+
+    /// The lowest optimization level this pass is enabled at by default.
+    pub level: u8,
+
+    /// The vector optimization.
+    pub optimize: fn(&mut Vec<Node>, &Options) -> bool,
+}
+
+impl VecPass {
+    /// Optimize the operations of the routines in the tree with the given
+    /// `root`, for a program run with the given `options`.
+    /// `true` is returned if the tree changed.
+    fn optimize_tree(&self, root: &mut Node, options: &Options) -> bool {
+        let mut changed = false;
+        if let Some(ops) = root.op.body_mut() {
+            // Optimize the contained routines first
+            for node in ops.iter_mut() {
+                changed |= self.optimize_tree(node, options);
+            }
+
+            changed |= (self.optimize)(ops, options);
+        }
+        changed
+    }
+}
+
+impl Pass for VecPass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn level(&self) -> u8 {
+        self.level
+    }
+
+    fn run(&self, root: &mut Node, options: &Options) -> bool {
+        self.optimize_tree(root, options)
+    }
+}
+
+/// A pass optimizing the operation tree of a whole program at once.
+///
+/// The optimization is applied on the root of the tree, for a program run
+/// with the given options. It optimizes the tree in place, and returns `true`
+/// if it changed.
+pub struct TreePass {
+    /// The name of this pass.
+    pub name: &'static str,
+
+    /// The lowest optimization level this pass is enabled at by default.
+    pub level: u8,
+
+    /// The tree optimization.
+    pub optimize: fn(&mut Node, &Options) -> bool,
+}
+
+impl Pass for TreePass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn level(&self) -> u8 {
+        self.level
+    }
+
+    fn run(&self, root: &mut Node, options: &Options) -> bool {
+        (self.optimize)(root, options)
+    }
+}
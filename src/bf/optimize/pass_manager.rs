@@ -0,0 +1,215 @@
+use super::super::{Node, Options};
+
+use super::pass::{Pass, RoutinePass, TreePass, VecPass};
+use super::routine::*;
+use super::tree::*;
+use super::vec::*;
+
+
+
+/// The highest optimization level.
+pub const MAX_LEVEL: u8 = 3;
+
+/// The names of all available passes, in the order they run in.
+pub const PASSES: &[&str] = &[
+    "div-mod",
+    "zero",
+    "add-and-zero",
+    "product",
+    "scan",
+    "dead-loop",
+    "set",
+    "defer-seek",
+    "if",
+    "canonicalize",
+    "block",
+    "partial-eval",
+];
+
+/// The maximum number of times to run all passes, when running them until
+/// the operation tree doesn't change anymore.
+const MAX_ROUNDS: usize = 16;
+
+/// Get the available pass with the given `name`.
+///
+/// `None` is returned if there is no such pass.
+pub fn pass(name: &str) -> Option<Box<dyn Pass>> {
+    let pass: Box<dyn Pass> = match name {
+        "div-mod" => Box::new(RoutinePass {
+            name: "div-mod",
+            level: 2,
+            optimize: optimize_div_mod,
+        }),
+        "zero" => Box::new(RoutinePass {
+            name: "zero",
+            level: 1,
+            optimize: optimize_zero,
+        }),
+        "add-and-zero" => Box::new(RoutinePass {
+            name: "add-and-zero",
+            level: 2,
+            optimize: optimize_add_and_zero,
+        }),
+        "product" => Box::new(RoutinePass {
+            name: "product",
+            level: 2,
+            optimize: optimize_product,
+        }),
+        "scan" => Box::new(RoutinePass {
+            name: "scan",
+            level: 1,
+            optimize: optimize_scan,
+        }),
+        "dead-loop" => Box::new(TreePass {
+            name: "dead-loop",
+            level: 2,
+            optimize: optimize_dead_loop,
+        }),
+        "set" => Box::new(VecPass {
+            name: "set",
+            level: 1,
+            optimize: optimize_set,
+        }),
+        "defer-seek" => Box::new(VecPass {
+            name: "defer-seek",
+            level: 2,
+            optimize: optimize_defer_seek,
+        }),
+        "if" => Box::new(VecPass {
+            name: "if",
+            level: 2,
+            optimize: optimize_if,
+        }),
+        "canonicalize" => Box::new(VecPass {
+            name: "canonicalize",
+            level: 1,
+            optimize: optimize_canonicalize,
+        }),
+        "block" => Box::new(VecPass {
+            name: "block",
+            level: 2,
+            optimize: optimize_block,
+        }),
+        "partial-eval" => Box::new(TreePass {
+            name: "partial-eval",
+            level: 3,
+            optimize: optimize_partial_eval,
+        }),
+        _ => return None,
+    };
+    Some(pass)
+}
+
+/// A pass manager, running a list of optimization passes over a program
+/// operation tree.
+///
+/// Optimization levels select the default passes:
+/// - `0`: no passes.
+/// - `1`: simple passes, replacing clear and scan loops, setting cells, and
+///   removing operations without effect.
+/// - `2`: all passes, but evaluating the start of programs.
+/// - `3`: all passes, and evaluating the start of programs at compile time.
+///   Repeated until the tree doesn't change anymore. Only evaluating relies
+///   on programs starting with zeroed memory, and only if
+///   `Options::fresh_memory` is set.
+pub struct PassManager {
+    /// The passes to run, in order.
+    passes: Vec<Box<dyn Pass>>,
+
+    /// Whether to repeat the passes until the tree doesn't change anymore.
+    fixpoint: bool,
+}
+
+impl PassManager {
+    /// Create a pass manager without any passes.
+    pub fn new() -> PassManager {
+        PassManager {
+            passes: vec![],
+            fixpoint: false,
+        }
+    }
+
+    /// Create a pass manager with the passes of the given optimization
+    /// `level`.
+    pub fn with_level(level: u8) -> PassManager {
+        PassManager {
+            passes: PASSES.iter()
+                .filter_map(|name| pass(name))
+                .filter(|pass| pass.level() <= level)
+                .collect(),
+            fixpoint: level >= MAX_LEVEL,
+        }
+    }
+
+    /// Create a pass manager with the passes selected in `options`.
+    ///
+    /// These are the passes of `Options::opt_level`, along with the passes
+    /// in `Options::enable_passes`, without the passes in
+    /// `Options::disable_passes`. Unknown pass names are ignored.
+    pub fn from_options(options: &Options) -> PassManager {
+        let listed = |list: &[String], name: &str| list.iter().any(|item| item == name);
+        PassManager {
+            passes: PASSES.iter()
+                .filter_map(|name| pass(name))
+                .filter(|pass| {
+                    (pass.level() <= options.opt_level || listed(&options.enable_passes, pass.name()))
+                        && !listed(&options.disable_passes, pass.name())
+                })
+                .collect(),
+            fixpoint: options.opt_level >= MAX_LEVEL,
+        }
+    }
+
+    /// Add the given pass, to run after all current passes.
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    /// Get the names of the passes to run, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Run the passes on the operation tree with the given `root`, for a
+    /// program run with the given `options`.
+    ///
+    /// The tree is optimized in place, `true` is returned if it changed.
+    pub fn run(&self, root: &mut Node, options: &Options) -> bool {
+        let mut changed = false;
+        for _ in 0..MAX_ROUNDS {
+            // Run all passes, stop if nothing changed or if not repeating
+            let mut round = false;
+            for pass in &self.passes {
+                round |= pass.run(root, options);
+            }
+            changed |= round;
+            if !round || !self.fixpoint {
+                break;
+            }
+        }
+        changed
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> PassManager {
+        PassManager::new()
+    }
+}
+
+
+
+/// Test whether passes are selected by level, and by name.
+#[test]
+fn test_pass_selection() {
+    assert!(PassManager::with_level(0).names().is_empty());
+    assert_eq!(PassManager::with_level(1).names(), vec!["zero", "scan", "set", "canonicalize"]);
+    assert_eq!(PassManager::with_level(2).names(), &PASSES[..PASSES.len() - 1]);
+    assert_eq!(PassManager::with_level(3).names(), PASSES);
+
+    let mut options = Options::default(true, false, false, false);
+    options.opt_level = 1;
+    options.enable_passes = vec!["defer-seek".into()];
+    options.disable_passes = vec!["zero".into(), "set".into(), "canonicalize".into()];
+    assert_eq!(PassManager::from_options(&options).names(), vec!["scan", "defer-seek"]);
+}
@@ -9,18 +9,35 @@
 //! - Other cells can be at any position.
 //! - To various cells a value may be added or subtracted with a different
 //!   factor.
+//! - The current cell may change by any step in each iteration.
+//! - Cells may be touched in any order, and any number of times.
 //!
 //! This optimization therefore covers many forms of copy and multiplication
-//! routines.
+//! routines. The routine is recognized by a summary of a single iteration,
+//! being the total amount each cell changes by. Routines with the same summary
+//! are optimized into the same operation.
+//!
+//! The number of iterations is determined exactly when executing, using
+//! modular arithmetic for the cell width. For odd steps this uses the modular
+//! inverse of the step, for even steps the loop only ends for some values.
+//!
+//! Unless cells wrap, a cell may saturate or trap before the total amount is
+//! added. Routines are then only optimized if they change the base cell
+//! first, and each cell once.
+//!
+//! Moving the pointer past an end of the tape that clamps, wraps or can't grow
+//! changes the cells reached after it. The operation therefore checks whether
+//! all cells are on the tape when running, and runs the original routine
+//! otherwise. The lowest and highest cell the routine reaches are kept as
+//! targets for this, even if they don't change.
 //!
 //! # Optimization requirements
-//! - There must be at least 4 operations.
-//! - Subtraction must be the first operation.
-//! - The following operations must be any number of the following sequence:
-//!     - Seek to another cell to add to or subtract from
-//!     - Add or subtract
-//! - The last operation must seek back to the base cell.
-//! - The base cell must never be touched by an other operation.
+//! - A routine that must only contain add, subtract, seek and block
+//!   operations.
+//! - The seek operations must move the pointer back to the base cell in total.
+//! - The base cell must change in total.
+//! - Unless cells wrap, the base cell must change first, and each cell must
+//!   change once.
 //!
 //! # Example routines
 //! - `[->+<]'
@@ -41,15 +58,29 @@
 //! - `[->+>++>-<<<]`
 //!     - Add the current value to the next cell
 //!     - Add the current value to the 2nd next cell times 2
-//!     - Zero the current and 3rd cell
+//!     - Subtract the current value from the 3rd next cell
+//!     - Zero the current cell
 //! - `[--->+<<+++>]`
-//!     - Add a 1/3e of the current cell to the next cell
-//!     - Add the current cell value to the previous cell
+//!     - Add the current value times the inverse of 3 to the next cell
+//!     - Add the current value times the inverse of 3, times 3, to the
+//!       previous cell
+//!     - Zero the current cell
+//! - `[+>-<]`
+//!     - Add the current cell value to the next cell, through wrapping
+//!     - Zero the current cell
+//! - `[>++>+<<-]`
+//!     - Add the current value to the next cell times 2
+//!     - Add the current value to the 2nd next cell
+//!     - Zero the current cell
+//! - `[>+<->+<]`
+//!     - Add the current value to the next cell times 2
 //!     - Zero the current cell
 
 
 
-use super::super::super::Op;
+use std::collections::BTreeMap;
+
+use super::super::super::{Arithmetic, Node, Op, Options};
 
 
 
@@ -59,81 +90,72 @@ use super::super::super::Op;
 /// True or false should be given to `cond` depending on whether the routine
 /// is conditional or not.
 /// The operations contained by the routine should be given to `ops`.
+/// The `options` the program is run with select which routines are
+/// optimized.
 ///
 /// If `Some` is returned, the whole routine should be replaced by it's
 /// contents.
-pub fn optimize_add_and_zero(cond: bool, ops: &Vec<Op>) -> Option<Op> {
-    // Do not run if this isn't a conditional loop,
-    // there must be at least six operations
-    if !cond || ops.len() < 4 {
+pub fn optimize_add_and_zero(cond: bool, ops: &[Node], options: &Options) -> Option<Op> {
+    // Do not run if this isn't a conditional loop
+    if !cond {
         return None;
     }
 
-    // Create a operations iterator
-    let mut iter = ops.iter().enumerate();
-
-    // The first cell must subtract
-    let step;
-    match iter.next() {
-        Some((_, &Op::Inc(amount))) if amount < 0 => step = -amount,
-        _ => return None,
-    }
-
-    // Create a vector for relative memory positions to copy to,
-    // and a variable that remembers the current offset
-    let mut targets = Vec::with_capacity((ops.len() - 2) / 2);
+    // Summarize a single iteration, the total amount each cell relative to the
+    // base cell changes by, and the offset the pointer ends at. Remember the
+    // order cells are changed in, and the lowest and highest cell reached.
+    let mut deltas = BTreeMap::new();
+    let mut order = vec![];
     let mut offset = 0;
-
-    // Find cells this data is moved into
-    loop {
-        // Get the seek and subtract operators
-        let (_, seek_op) = iter.next()?;
-        let (sub_i, sub_op) = iter.next()?;
-
-        // This must be a seek, modify the offste
-        match seek_op {
-            &Op::Seek(amount) => offset += amount,
+    let (mut low, mut high) = (0, 0);
+    for node in ops {
+        let mut add = |at: isize, amount: isize| {
+            *deltas.entry(at).or_insert(0) += amount;
+            order.push(at);
+        };
+        match node.op {
+            Op::Seek(amount) => offset += amount,
+            Op::Inc(amount) => add(offset, amount),
+            Op::IncAt(at, amount) => add(offset + at, amount),
+            Op::Block(ref block, seek, _) => {
+                for &(at, amount) in block {
+                    add(offset + at, amount);
+                }
+                offset += seek;
+            },
             _ => return None,
         }
+        low = low.min(offset);
+        high = high.max(offset);
+    }
+    for &at in &order {
+        low = low.min(at);
+        high = high.max(at);
+    }
 
-        // This must add/subtract, remember the factor to do it with
-        let factor;
-        match sub_op {
-            &Op::Inc(amount) =>
-                factor = if amount != 0 {
-                    amount as f32 / step as f32
-                } else {
-                    0f32
-                },
-            _ => return None,
-        }
-
-        // We may not overwrite the base cell
-        if offset == 0 {
-            return None;
-        }
-
-        // Add the current offset to the target list
-        if factor != 0f32 {
-            targets.push((offset, factor));
-        }
-
-        // If the subtraction was the second last operator,
-        // the last must set the offset back to zero
-        if sub_i == ops.len() - 2 {
-            // Get the reset operator
-            let (_, reset_op) = iter.next()?;
+    // The pointer must end at the base cell, which must change
+    let step = deltas.remove(&0).unwrap_or(0);
+    if offset != 0 || step == 0 {
+        return None;
+    }
 
-            // This must be addition
-            match reset_op {
-                &Op::Seek(amount) if amount == -offset=> {},
-                _ => return None,
-            }
+    // Unless cells wrap, the base cell must change first, and each cell once
+    let once = order.iter().enumerate().all(|(index, at)| !order[..index].contains(at));
+    if options.arithmetic != Arithmetic::Wrap && (order.first() != Some(&0) || !once) {
+        return None;
+    }
 
-            // This optimization is succesful, return the resulting operator
-            return Some(
-                Op::AddAndZero(targets),
-            );
+    // Keep the lowest and highest cell, so they are checked to be on the tape
+    for &at in &[low, high] {
+        if at != 0 {
+            deltas.entry(at).or_insert(0);
         }
     }
+
+    // This optimization is succesful, return the resulting operator
+    Some(Op::AddAndZero(
+        step,
+        deltas.into_iter().filter(|&(at, amount)| amount != 0 || at == low || at == high).collect(),
+        Box::new(Op::Routine(ops.to_vec(), true)),
+    ))
 }
@@ -0,0 +1,155 @@
+//! # Division and modulo optimization
+//! This optimization optimizes the well known routine dividing the current
+//! cell value by a divisor, giving both the quotient and the remainder.
+//! This routine is common in programs printing numbers, and normally takes
+//! as many iterations as the current cell value, each running a couple of
+//! inner routines.
+//!
+//! Unlike other routine optimizations, this one doesn't summarize the
+//! routine. The inner routines move the pointer depending on cell values,
+//! which no summary covers. Instead, the routine is matched against a list
+//! of known layouts, both as written and mirrored, before any other
+//! optimization changes it.
+//!
+//! The routine is replaced by a division and modulo operation. This
+//! determines the results at once if the cells around the divisor are laid
+//! out as the routine expects. Otherwise it runs the original routine, which
+//! the operation keeps.
+//!
+//! # Optimization requirements
+//! - Cells must wrap, the closed form relies on modular arithmetic.
+//! - A routine that must match one of the known layouts exactly.
+//!
+//! # Example routines
+//! - `[->-[>+>>]>[+[-<+>]>+>>]<<<<<]`
+//!     - Divide the current cell value by the next cell value
+//!     - Put the divisor minus the remainder in the next cell, the remainder
+//!       in the 2nd next cell, and add the quotient to the 3rd next cell
+//!     - Zero the current cell
+//! - `[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]`
+//!     - Add the current cell value to the next cell
+//!     - Divide the current cell value by the 2nd next cell value
+//!     - Put the divisor minus the remainder in the 2nd next cell, the
+//!       remainder in the 3rd next cell, and add the quotient to the 4th next
+//!       cell
+//!     - Zero the current cell
+//! - `[-<-[<+<<]<[+[->+<]<+<<]>>>>>]`
+//!     - Divide the current cell value by the previous cell value, mirrored
+
+
+
+use super::super::super::{Arithmetic, Node, Op, Options};
+
+
+
+/// The known division and modulo routines.
+const LAYOUTS: &[Layout] = &[
+    Layout {
+        body: "->-[>+>>]>[+[-<+>]>+>>]<<<<<",
+        divisor: 1,
+        targets: &[],
+    },
+    Layout {
+        body: "->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<",
+        divisor: 2,
+        targets: &[(1, 1)],
+    },
+];
+
+/// A known division and modulo routine.
+struct Layout {
+    /// The operations contained by the routine, as brainfuck source.
+    body: &'static str,
+
+    /// The relative offset of the divisor cell.
+    divisor: isize,
+
+    /// The relative targets, along with the amount added to each in each
+    /// iteration.
+    targets: &'static [(isize, isize)],
+}
+
+/// Optimize division and modulo routines.
+///
+/// This optimization is applied on routines.
+/// True or false should be given to `cond` depending on whether the routine
+/// is conditional or not.
+/// The operations contained by the routine should be given to `ops`.
+/// The `options` the program is run with must use wrapping arithmetic.
+///
+/// If `Some` is returned, the whole routine should be replaced by it's
+/// contents.
+pub fn optimize_div_mod(cond: bool, ops: &[Node], options: &Options) -> Option<Op> {
+    // Do not run if this isn't a conditional loop, or if cells don't wrap
+    if !cond || options.arithmetic != Arithmetic::Wrap {
+        return None;
+    }
+
+    // Write the routine back as source, only plain operations are allowed
+    let limit = LAYOUTS.iter().map(|layout| layout.body.len()).max().unwrap_or(0);
+    let mut body = String::new();
+    if !source(ops, &mut body, limit) {
+        return None;
+    }
+
+    // Find the matching layout, possibly mirrored
+    let mirrored = mirror(&body);
+    for layout in LAYOUTS {
+        let direction = if body == layout.body {
+            1
+        } else if mirrored == layout.body {
+            -1
+        } else {
+            continue;
+        };
+
+        // This optimization is succesful, return the resulting operator
+        return Some(Op::DivMod(
+            layout.divisor * direction,
+            layout.targets.iter().map(|&(target, amount)| (target * direction, amount)).collect(),
+            Box::new(Op::Routine(ops.to_vec(), true)),
+        ));
+    }
+
+    None
+}
+
+/// Write the given operations as brainfuck source to `out`.
+///
+/// `false` is returned if an operation has no plain brainfuck equivalent
+/// without input or output, or if the source gets longer than `limit`.
+fn source(ops: &[Node], out: &mut String, limit: usize) -> bool {
+    for node in ops {
+        match node.op {
+            Op::Seek(amount) | Op::Inc(amount) if out.len() + amount.unsigned_abs() > limit => return false,
+            Op::Seek(amount) => {
+                let token = if amount < 0 { '<' } else { '>' };
+                out.extend((0..amount.abs()).map(|_| token));
+            },
+            Op::Inc(amount) => {
+                let token = if amount < 0 { '-' } else { '+' };
+                out.extend((0..amount.abs()).map(|_| token));
+            },
+            Op::Routine(ref inner, true) => {
+                out.push('[');
+                if !source(inner, out, limit) {
+                    return false;
+                }
+                out.push(']');
+            },
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Mirror the given brainfuck source, swapping the seek directions.
+fn mirror(source: &str) -> String {
+    source.chars()
+        .map(|token| match token {
+            '<' => '>',
+            '>' => '<',
+            token => token,
+        })
+        .collect()
+}
@@ -1,6 +1,12 @@
 mod add_and_zero;
+mod div_mod;
+mod product;
+mod scan;
 mod zero;
 
 // Reexport
 pub use self::add_and_zero::optimize_add_and_zero;
+pub use self::div_mod::optimize_div_mod;
+pub use self::product::optimize_product;
+pub use self::scan::optimize_scan;
 pub use self::zero::optimize_zero;
@@ -0,0 +1,268 @@
+//! # Product optimization
+//! This optimization optimizes routines that add the product of the current
+//! cell value and some other cells to a number of cells, and then zero their
+//! own cell.
+//! These routines are common for multiplying numbers, with inner loops that
+//! move or copy a cell in each iteration. The inner loops are already replaced
+//! by add and zero operations, but the routine still takes quadratic work.
+//!
+//! The routine is recognized by a summary of a single iteration, expressing
+//! the value of each cell afterwards in the values of the cells before it.
+//! Inner add and zero operations add a multiple of a cell value to other
+//! cells this way. After the first iteration, cells that are set to a
+//! constant keep that value, which makes temporary cells used to restore a
+//! copied cell keep their value as well. All other iterations then add the
+//! same amount to each cell.
+//!
+//! The routine is replaced by a product operation. This runs the first
+//! iteration as summarized, and all other iterations at once by multiplying
+//! the amount they add by their number.
+//!
+//! Moving the pointer past an end of the tape that clamps, wraps or can't grow
+//! changes the cells reached after it. The operation therefore checks whether
+//! the cells of the first iteration are on the tape when running, and runs the
+//! original routine otherwise.
+//!
+//! # Optimization requirements
+//! - Cells must wrap, the closed form relies on modular arithmetic.
+//! - A routine that must only contain add, subtract, seek, block, set and
+//!   add and zero operations.
+//! - Add and zero operations must change their cell by one in each
+//!   iteration.
+//! - The seek operations must move the pointer back to the base cell in total.
+//! - The pointer must not move past the cells of the first iteration.
+//! - The base cell must change by a constant amount.
+//! - Other cells must be set to a constant, or be increased by an amount that
+//!   doesn't depend on their own value.
+//! - After the first iteration, the amount added to each cell must only
+//!   depend on cells that don't change anymore, and must depend on at least
+//!   one of them.
+//!
+//! # Example routines
+//! - `[->[->+>+<<]>>[-<<+>>]<<<]`
+//!     - Add the product of the current and next cell to the 2nd next cell
+//!     - Restore the next cell through the 3rd next cell, if that is zero
+//!     - Zero the current cell
+//! - `[->[->+>+<<]>[-<+>]<<]`
+//!     - Add the product of the current and next cell to the 3rd next cell
+//!     - Restore the next cell through the 2nd next cell, if that is zero
+//!     - Zero the current cell
+//! - `[->>[-<+>]<[->+>+<<]<]`
+//!     - Add the product of the current cell and the sum of the next two cells
+//!       to the 3rd next cell
+//!     - Move the sum into the 2nd next cell, and zero the next cell
+//!     - Zero the current cell
+
+
+
+use std::collections::BTreeMap;
+
+use super::super::super::{Arithmetic, Node, Op, Options};
+
+
+
+/// Optimize product routines.
+///
+/// This optimization is applied on routines.
+/// True or false should be given to `cond` depending on whether the routine
+/// is conditional or not.
+/// The operations contained by the routine should be given to `ops`.
+/// The `options` the program is run with must use wrapping arithmetic.
+///
+/// If `Some` is returned, the whole routine should be replaced by it's
+/// contents.
+pub fn optimize_product(cond: bool, ops: &[Node], options: &Options) -> Option<Op> {
+    // Do not run if this isn't a conditional loop, or if cells don't wrap
+    if !cond || options.arithmetic != Arithmetic::Wrap {
+        return None;
+    }
+
+    // Summarize a single iteration, the value of each touched cell relative
+    // to the base cell in terms of the values before it
+    let mut cells = BTreeMap::new();
+    let mut offset = 0;
+    let (mut low, mut high) = (0, 0);
+    for node in ops {
+        match node.op {
+            Op::Seek(amount) => offset += amount,
+            Op::Inc(amount) => value(&mut cells, offset).inc(amount),
+            Op::IncAt(at, amount) => value(&mut cells, offset + at).inc(amount),
+            Op::Block(ref deltas, seek, _) => {
+                for &(at, amount) in deltas {
+                    value(&mut cells, offset + at).inc(amount);
+                }
+                offset += seek;
+            },
+            Op::Zero => {
+                cells.insert(offset, Value::constant(0));
+            },
+            Op::Set(amount) => {
+                cells.insert(offset, Value::constant(amount));
+            },
+            Op::SetAt(at, amount) => {
+                cells.insert(offset + at, Value::constant(amount));
+            },
+            Op::AddAndZero(step, ref targets, _) if step.abs() == 1 => {
+                // The loop runs the cell value times, negated if it counts up
+                let count = value(&mut cells, offset).scaled(-step);
+                for &(at, amount) in targets {
+                    value(&mut cells, offset + at).add(&count, amount);
+                }
+                cells.insert(offset, Value::constant(0));
+            },
+            _ => return None,
+        }
+        low = low.min(offset);
+        high = high.max(offset);
+    }
+    for &at in cells.keys() {
+        low = low.min(at);
+        high = high.max(at);
+    }
+
+    // The pointer must end at the base cell, which must change by a constant
+    let step = match cells.remove(&0) {
+        Some(ref base) if base.terms == single(0, 1) => base.constant,
+        _ => return None,
+    };
+    if offset != 0 || step == 0 {
+        return None;
+    }
+
+    // Split the cells into those set to a constant, and those increased by
+    // an amount. Drop cells that end up unchanged.
+    let mut constants = BTreeMap::new();
+    let mut deltas = BTreeMap::new();
+    for (offset, mut value) in cells {
+        match value.terms.remove(&offset) {
+            None if value.terms.is_empty() => {
+                constants.insert(offset, value.constant);
+            },
+            Some(1) if value.constant != 0 || !value.terms.is_empty() => {
+                deltas.insert(offset, value);
+            },
+            Some(1) => {},
+            _ => return None,
+        }
+    }
+
+    // The first iteration sets cells to their constant, which is the same as
+    // adding the difference
+    let mut first: Vec<_> = constants.iter()
+        .map(|(&offset, &constant)| (offset, constant, vec![(offset, -1)]))
+        .chain(deltas.iter().map(|(&offset, delta)| (offset, delta.constant, delta.list())))
+        .collect();
+    first.sort_by_key(|&(offset, _, _)| offset);
+
+    // In all other iterations the constant cells keep their value, determine
+    // what the other cells are increased by
+    let rest: BTreeMap<isize, Value> = deltas.iter()
+        .map(|(&offset, delta)| (offset, delta.substituted(&constants)))
+        .filter(|(_, delta)| delta.constant != 0 || !delta.terms.is_empty())
+        .collect();
+
+    // The pointer must not move past the cells of the first iteration, which
+    // are checked to be on the tape
+    let reached = first.iter()
+        .flat_map(|&(target, _, ref terms)| Some(target).into_iter().chain(terms.iter().map(|&(at, _)| at)))
+        .chain(Some(0));
+    if reached.clone().min() > Some(low) || reached.max() < Some(high) {
+        return None;
+    }
+
+    // These amounts must only depend on cells that don't change anymore, and
+    // must not be constant as an add and zero operation covers that
+    let fixed = |offset: &isize| *offset != 0 && !rest.contains_key(offset);
+    if rest.values().any(|delta| !delta.terms.keys().all(&fixed))
+        || rest.values().all(|delta| delta.terms.is_empty())
+    {
+        return None;
+    }
+
+    // This optimization is succesful, return the resulting operator
+    Some(Op::Product(
+        step,
+        first,
+        rest.iter().map(|(&offset, delta)| (offset, delta.constant, delta.list())).collect(),
+        Box::new(Op::Routine(ops.to_vec(), true)),
+    ))
+}
+
+/// The value of a memory cell, as a sum of a constant and multiples of the
+/// values of cells at relative offsets.
+///
+/// All arithmetic wraps, as cell values do.
+#[derive(Clone, PartialEq)]
+struct Value {
+    /// The constant amount.
+    constant: isize,
+
+    /// The relative cell offsets, along with their factor.
+    terms: BTreeMap<isize, isize>,
+}
+
+impl Value {
+    /// A value that is the given `constant`.
+    fn constant(constant: isize) -> Value {
+        Value {
+            constant,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    /// This value multiplied by the given `factor`.
+    fn scaled(&self, factor: isize) -> Value {
+        let mut value = Value::constant(0);
+        value.add(self, factor);
+        value
+    }
+
+    /// Increase the constant of this value by the given `amount`.
+    fn inc(&mut self, amount: isize) {
+        self.constant = self.constant.wrapping_add(amount);
+    }
+
+    /// Add the `other` value multiplied by the given `factor` to this value.
+    fn add(&mut self, other: &Value, factor: isize) {
+        self.constant = self.constant.wrapping_add(other.constant.wrapping_mul(factor));
+        for (&offset, &term) in &other.terms {
+            let total = self.terms.get(&offset).cloned().unwrap_or(0)
+                .wrapping_add(term.wrapping_mul(factor));
+            if total == 0 {
+                self.terms.remove(&offset);
+            } else {
+                self.terms.insert(offset, total);
+            }
+        }
+    }
+
+    /// This value with the cells in `constants` replaced by their constant.
+    fn substituted(&self, constants: &BTreeMap<isize, isize>) -> Value {
+        let mut value = Value::constant(self.constant);
+        for (&offset, &factor) in &self.terms {
+            match constants.get(&offset) {
+                Some(&constant) => value.add(&Value::constant(constant), factor),
+                None => value.add(&Value { constant: 0, terms: single(offset, 1) }, factor),
+            }
+        }
+        value
+    }
+
+    /// The terms of this value, as a sorted list.
+    fn list(&self) -> Vec<(isize, isize)> {
+        self.terms.iter().map(|(&offset, &factor)| (offset, factor)).collect()
+    }
+}
+
+/// Get the value of the cell at the given `offset` in the summary `cells`.
+/// Cells that weren't touched yet have their value from before.
+fn value(cells: &mut BTreeMap<isize, Value>, offset: isize) -> &mut Value {
+    cells.entry(offset).or_insert_with(|| Value { constant: 0, terms: single(offset, 1) })
+}
+
+/// Terms with just the cell at the given `offset`, with the given `factor`.
+fn single(offset: isize, factor: isize) -> BTreeMap<isize, isize> {
+    let mut terms = BTreeMap::new();
+    terms.insert(offset, factor);
+    terms
+}
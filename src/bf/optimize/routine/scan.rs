@@ -0,0 +1,46 @@
+//! # Scan routine optimization
+//! This optimizes routines that seek to the next zero cell,
+//! and replaces the routine with a single instruction.
+//!
+//! Searching for a zero cell is common to find the end of a data structure on
+//! the tape, and can take many iterations. The replacing instruction searches
+//! the memory at once.
+//!
+//! # Optimization requirements
+//! - A routine that must only contain a single seek operation.
+//!
+//! # Example routines
+//! - `[>]'
+//!     - Seek right to the next zero cell
+//! - `[<]`
+//!     - Seek left to the next zero cell
+//! - `[>>>>]`
+//!     - Seek right to the next zero cell, in steps of 4
+
+
+
+use super::super::super::{Node, Op, Options};
+
+
+
+/// Optimize scanning routines.
+///
+/// This optimization is applied on routines.
+/// True or false should be given to `cond` depending on whether the routine
+/// is conditional or not.
+/// The operations contained by the routine should be given to `ops`.
+///
+/// If `Some` is returned, the whole routine should be replaced by it's
+/// contents.
+pub fn optimize_scan(cond: bool, ops: &[Node], _: &Options) -> Option<Op> {
+    // Do not run if this isn't a conditional loop
+    if !cond || ops.len() != 1 {
+        return None;
+    }
+
+    // The only operation must seek
+    match ops[0].op {
+        Op::Seek(stride) if stride != 0 => Some(Op::Scan(stride)),
+        _ => None,
+    }
+}
@@ -8,7 +8,18 @@
 //! If a memory cell is an odd number, and a routine constantly adds an even
 //! number to the cell it would normally be possible to create an infinite
 //! routine as zero would never be reached. This optimization ignores this
-//! and zero's te cell.
+//! and zero's te cell, unless strict optimization is enabled.
+//!
+//! Cells that trap overflow before reaching zero, unless the routine steps
+//! down by one. Such routines are never zeroed, as that would skip the error.
+//! They are handled as with strict optimization instead.
+//!
+//! With strict optimization, routines that may not reach zero are replaced
+//! with an add and zero operation without targets instead. That operation
+//! zeroes the cell if zero is reachable, and loops forever or traps
+//! otherwise, the same way the routine would. Looping forever still consumes
+//! fuel in each iteration. Routines that never change the cell, or that
+//! contain multiple operations while cells don't wrap, are kept.
 //!
 //! # Optimization requirements
 //! - A routine that must only contain add and subtract operations.
@@ -22,10 +33,14 @@
 //!     - Zero the current cell
 //! - `[++-]`
 //!     - Zero the current cell
+//! - `[++]`
+//!     - Zero the current cell
+//!     - With strict optimization: zero the current cell if it is even, loop
+//!       forever if it is odd
 
 
 
-use super::super::super::Op;
+use super::super::super::{Arithmetic, Node, Op, Options};
 
 
 
@@ -35,24 +50,44 @@ use super::super::super::Op;
 /// True or false should be given to `cond` depending on whether the routine
 /// is conditional or not.
 /// The operations contained by the routine should be given to `ops`.
+/// The `options` the program is run with select strict optimization.
 ///
 /// If `Some` is returned, the whole routine should be replaced by it's
 /// contents.
-pub fn optimize_zero(cond: bool, ops: &Vec<Op>) -> Option<Op> {
+pub fn optimize_zero(cond: bool, ops: &[Node], options: &Options) -> Option<Op> {
     // Do not run if this isn't a conditional loop
     if !cond {
         return None;
     }
 
-    // Check whether we can optimize
-    if ops.iter().all(
-        |op| match *op {
-            Op::Inc(..) => true,
-            _ => false,
+    // Check whether we can optimize, and determine the total step
+    let mut step = 0;
+    for node in ops {
+        match node.op {
+            Op::Inc(amount) => step += amount,
+            _ => return None,
         }
-    ) {
+    }
+    if !options.strict && options.arithmetic != Arithmetic::Trap {
+        return Some(Op::Zero);
+    }
+
+    // Keep routines that loop forever, or that may overflow between their
+    // operations
+    let arithmetic = options.arithmetic;
+    if step == 0 || (arithmetic != Arithmetic::Wrap && ops.len() != 1) {
+        return None;
+    }
+
+    // Zero if the routine always reaches zero, keep the loop exact otherwise
+    let terminates = match arithmetic {
+        Arithmetic::Wrap => step % 2 != 0,
+        Arithmetic::Saturate => step < 0,
+        Arithmetic::Trap => step == -1,
+    };
+    if terminates {
         Some(Op::Zero)
     } else {
-        None
+        Some(Op::AddAndZero(step, vec![], Box::new(Op::Routine(ops.to_vec(), true))))
     }
 }
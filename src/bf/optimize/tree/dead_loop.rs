@@ -0,0 +1,222 @@
+//! # Dead loop optimization
+//! This optimization removes loops that can never run, because the current
+//! cell is known to be zero where the loop starts.
+//!
+//! At the start of a program the whole tape is zero, and right after a loop
+//! the current cell is zero. Programs commonly start with a comment loop, and
+//! loops are often placed right after each other.
+//!
+//! Cells known to be zero are tracked through the whole program, until an
+//! operation with an unknown effect is reached. Operations zeroing a cell that
+//! is known to be zero are removed as well.
+//!
+//! Moving the pointer past an end of the tape that clamps or wraps makes cell
+//! positions unknown, and past an end that errors or can't grow stops the
+//! program right there. Cells are then only tracked if the pointer can't
+//! reach such an end.
+//!
+//! The whole tape is only known to be zero at the start if the program runs
+//! on fresh memory, see `Options::fresh_memory`.
+//!
+//! # Example operations
+//! - `[comment.]+`
+//!     - Increase the current cell
+//! - `[->+<][<]`
+//!     - Add the current cell value to the next cell, and zero
+//! - `>[-]<+[-][-]`
+//!     - Increase the current cell
+//!     - Zero the current cell
+
+
+
+use std::collections::BTreeSet;
+
+use super::super::super::{Node, Op, Options};
+
+
+
+/// Optimize dead loops.
+///
+/// This optimization is applied on the root of the operation tree of a whole
+/// program, given to `root`. The program is run with the given `options`.
+///
+/// The tree is optimized in place, `true` is returned if it changed.
+pub fn optimize_dead_loop(root: &mut Node, options: &Options) -> bool {
+    let mut facts = if options.fresh_memory {
+        Facts::start()
+    } else {
+        Facts::unknown()
+    };
+    match root.op {
+        Op::Routine(ref mut ops, false) => optimize_ops(ops, &mut facts, options),
+        _ => false,
+    }
+}
+
+/// Remove operations from `ops` that have no effect, because of the given
+/// known `facts` at the first operation.
+///
+/// The facts are updated to those after the last operation.
+/// `true` is returned if the operations changed.
+fn optimize_ops(ops: &mut Vec<Node>, facts: &mut Facts, options: &Options) -> bool {
+    let mut changed = false;
+
+    ops.retain_mut(|node| {
+        let keep = match node.op {
+            // Skip loops that can't run, loop bodies start without facts
+            Op::Routine(_, true)
+            | Op::If(_)
+            | Op::Scan(_)
+            | Op::AddAndZero(..)
+            | Op::Product(..)
+            | Op::DivMod(..)
+            | Op::Zero if facts.zero(0, options) => false,
+            Op::Routine(ref mut inner, true) | Op::If(ref mut inner) => {
+                changed |= optimize_ops(inner, &mut Facts::unknown(), options);
+                *facts = Facts::unknown();
+                facts.write(0, true, options);
+                true
+            },
+            Op::Routine(ref mut inner, false) => {
+                changed |= optimize_ops(inner, facts, options);
+                true
+            },
+
+            // Skip zeroing cells that are zero
+            Op::Set(0) if facts.zero(0, options) => false,
+            Op::SetAt(offset, 0) if facts.zero(offset, options) => false,
+
+            // Track cell values
+            Op::Zero => facts.write(0, true, options),
+            Op::Set(amount) => facts.write(0, amount == 0, options),
+            Op::SetAt(offset, amount) => facts.write(offset, amount == 0, options),
+            Op::Inc(_) | Op::Input => facts.write(0, false, options),
+            Op::IncAt(offset, _) | Op::InputAt(offset) => facts.write(offset, false, options),
+            Op::Output | Op::OutputAt(_) => true,
+            Op::Seek(amount) => facts.seek(amount, options),
+            Op::Scan(_) | Op::DivMod(..) => {
+                *facts = Facts::unknown();
+                facts.write(0, true, options)
+            },
+            Op::AddAndZero(_, ref targets, _) => {
+                for &(target, _) in targets {
+                    facts.write(target, false, options);
+                }
+                facts.write(0, true, options)
+            },
+            Op::Product(_, ref first, _, _) => {
+                for &(target, _, ref terms) in first {
+                    for &(offset, _) in terms {
+                        facts.read(offset, options);
+                    }
+                    facts.write(target, false, options);
+                }
+                facts.write(0, true, options)
+            },
+            Op::Load(offset, ref values) => {
+                for (index, &value) in values.iter().enumerate() {
+                    facts.write(offset + index as isize, value == 0, options);
+                }
+                true
+            },
+            Op::Write(_) => true,
+            Op::Block(ref deltas, seek, _) => {
+                for &(offset, _) in deltas {
+                    facts.write(offset, false, options);
+                }
+                facts.seek(seek, options)
+            },
+        };
+
+        changed |= !keep;
+        keep
+    });
+
+    changed
+}
+
+/// Facts known about memory cells at some point in a program.
+struct Facts {
+    /// The position of the pointer relative to the cell it started at, if
+    /// known.
+    position: Option<isize>,
+
+    /// Whether all cells are known to be zero, except for those in `cells`.
+    /// If `false`, only the cells in `cells` are known to be zero.
+    all_zero: bool,
+
+    /// Cells relative to the pointer, see `all_zero`.
+    cells: BTreeSet<isize>,
+}
+
+impl Facts {
+    /// The facts at the start of a program, with all cells zero.
+    fn start() -> Facts {
+        Facts {
+            position: Some(0),
+            all_zero: true,
+            cells: BTreeSet::new(),
+        }
+    }
+
+    /// No known facts.
+    fn unknown() -> Facts {
+        Facts {
+            position: None,
+            all_zero: false,
+            cells: BTreeSet::new(),
+        }
+    }
+
+    /// Check whether the cell at the relative `offset` is known to be zero.
+    ///
+    /// Cells that weren't written are only known to be zero if they're reached
+    /// exactly, as the operation may otherwise grow the tape or fail.
+    fn zero(&self, offset: isize, options: &Options) -> bool {
+        if self.all_zero {
+            !self.cells.contains(&offset) && options.exact(self.position, offset)
+        } else {
+            self.cells.contains(&offset)
+        }
+    }
+
+    /// Read the cell at the relative `offset`. Forget all facts if the cell
+    /// isn't reached exactly, as the operation may then run step by step.
+    ///
+    /// Always returns `true`, to keep the reading operation.
+    fn read(&mut self, offset: isize, options: &Options) -> bool {
+        if !options.exact(self.position, offset) {
+            *self = Facts::unknown();
+        }
+        true
+    }
+
+    /// Write to the cell at the relative `offset`, which becomes zero or
+    /// unknown. Forget all facts if the cell isn't reached exactly.
+    ///
+    /// Always returns `true`, to keep the writing operation.
+    fn write(&mut self, offset: isize, zero: bool, options: &Options) -> bool {
+        if !options.exact(self.position, offset) {
+            *self = Facts::unknown();
+        } else if zero == self.all_zero {
+            self.cells.remove(&offset);
+        } else {
+            self.cells.insert(offset);
+        }
+        true
+    }
+
+    /// Seek the pointer for the relative `amount`. Forget all facts if the
+    /// pointer doesn't move exactly.
+    ///
+    /// Always returns `true`, to keep the seek operation.
+    fn seek(&mut self, amount: isize, options: &Options) -> bool {
+        if !options.exact(self.position, amount) {
+            *self = Facts::unknown();
+            return true;
+        }
+        self.position = self.position.map(|position| position + amount);
+        self.cells = self.cells.iter().map(|cell| cell - amount).collect();
+        true
+    }
+}
@@ -0,0 +1,6 @@
+mod dead_loop;
+mod partial_eval;
+
+// Reexport
+pub use self::dead_loop::optimize_dead_loop;
+pub use self::partial_eval::optimize_partial_eval;
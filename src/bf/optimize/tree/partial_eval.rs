@@ -0,0 +1,153 @@
+//! # Partial evaluation optimization
+//! This optimization runs the start of a program at compile time, up to the
+//! first operation that reads input.
+//!
+//! Many programs produce all or most of their output without reading input.
+//! The evaluated operations are replaced by writing the precomputed output,
+//! and loading the resulting memory cell values directly.
+//!
+//! Evaluation stops early when a step budget runs out, when an operation
+//! fails, or when an operation grows the tape, leaving the remaining
+//! operations to run as normal. The loaded cells don't grow the tape the
+//! program runs on, which changes where later operations end up past an end
+//! that wraps or clamps.
+//!
+//! This requires the program to run on fresh memory, see
+//! `Options::fresh_memory`.
+//!
+//! # Example operations
+//! - `++++++++[>++++++++<-]>+.,`
+//!     - Load 65 into the next cell
+//!     - Output `A`
+//!     - Seek to the next cell
+//!     - Read user input
+
+
+
+use std::io;
+
+use super::super::super::{Cell, CellWidth, Exit, Memory, Node, Op, Options, Span};
+
+
+
+/// The maximum number of steps to evaluate, see `Options::fuel`.
+const BUDGET: u64 = 1_000_000;
+
+/// Optimize the start of a program, by evaluating it.
+///
+/// This optimization is applied on the root of the operation tree of a whole
+/// program, given to `root`. The program is run with the given `options`,
+/// on fresh memory.
+///
+/// The tree is optimized in place, `true` is returned if it changed.
+pub fn optimize_partial_eval(root: &mut Node, options: &Options) -> bool {
+    // Do not run if the program may run on memory in any state
+    if !options.fresh_memory {
+        return false;
+    }
+
+    let ops = match root.op {
+        Op::Routine(ref mut ops, false) => ops,
+        _ => return false,
+    };
+
+    // Evaluate with the configured cell type
+    let (count, prefix) = match options.cell_width {
+        CellWidth::Bits8 => evaluate::<u8>(ops, options),
+        CellWidth::Bits16 => evaluate::<u16>(ops, options),
+        CellWidth::Bits32 => evaluate::<u32>(ops, options),
+        CellWidth::Bits64 => evaluate::<u64>(ops, options),
+    };
+
+    // Don't replace operations that were evaluated already
+    let evaluated = ops[..count].iter()
+        .all(|node| matches!(node.op, Op::Load(..) | Op::Write(_) | Op::Seek(_)));
+    if evaluated {
+        return false;
+    }
+
+    ops.splice(..count, prefix);
+    true
+}
+
+/// Evaluate the start of the given operations on fresh memory with cells of
+/// type `C`.
+///
+/// Return the number of evaluated operations, along with the operations to
+/// replace them with.
+fn evaluate<C: Cell>(ops: &[Node], options: &Options) -> (usize, Vec<Node>) {
+    let mut memory: Memory<C> = Memory::from_options(options);
+    let mut output = vec![];
+    let mut fuel = Some(BUDGET);
+    let mut count = 0;
+    let size = memory.cells().len();
+
+    for node in ops {
+        // Stop at the first operation reading input
+        if reads_input(&node.op) {
+            break;
+        }
+
+        // Stop if it runs out of fuel, fails or grows the tape
+        let length = output.len();
+        match node.execute(&mut memory, options, &mut io::empty(), &mut output, &mut fuel) {
+            Ok(Exit::Finished) if memory.cells().len() == size => count += 1,
+            _ => {
+                // The memory and output are left halfway the operation, drop
+                // its output and evaluate the operations before it again
+                output.truncate(length);
+                memory = Memory::from_options(options);
+                let mut fuel = Some(BUDGET);
+                for node in &ops[..count] {
+                    if node.execute(&mut memory, options, &mut io::empty(), &mut io::sink(), &mut fuel).is_err() {
+                        return (0, vec![]);
+                    }
+                }
+                break;
+            },
+        }
+    }
+
+    if count == 0 {
+        return (0, vec![]);
+    }
+
+    // Create the operations, spanning the evaluated operations
+    let span = ops[..count].iter()
+        .map(|node| node.span)
+        .fold(ops[0].span, Span::join);
+    let mut prefix = vec![];
+
+    // Load all non-zero cells, relative to the cell the pointer started at
+    let cells = memory.cells();
+    let first = cells.iter().position(|cell| *cell != C::default());
+    let last = cells.iter().rposition(|cell| *cell != C::default());
+    if let (Some(first), Some(last)) = (first, last) {
+        prefix.push(Node::new(
+            Op::Load(
+                first as isize - memory.origin() as isize,
+                cells[first..=last].iter().map(|cell| cell.to_u64()).collect(),
+            ),
+            span,
+        ));
+    }
+
+    // Write the output, and move the pointer to where it ended
+    if !output.is_empty() {
+        prefix.push(Node::new(Op::Write(output), span));
+    }
+    let seek = memory.pointer() as isize - memory.origin() as isize;
+    if seek != 0 {
+        prefix.push(Node::new(Op::Seek(seek), span));
+    }
+
+    (count, prefix)
+}
+
+/// Check whether the given operation may read input.
+fn reads_input(op: &Op) -> bool {
+    match *op {
+        Op::Input | Op::InputAt(_) => true,
+        _ => op.body().is_some_and(|ops| ops.iter().any(|node| reads_input(&node.op))),
+    }
+}
@@ -0,0 +1,122 @@
+//! # Block optimization
+//! This optimization collapses straight-line runs of operations that only add
+//! to cells and seek, into a single block operation.
+//!
+//! A block holds the total amount added to each cell, sorted by the offset of
+//! the cell, along with the total amount the pointer seeks by afterwards. The
+//! cells are then updated in a tight loop, rather than operation by operation.
+//!
+//! Like merging neighbouring additions, each cell is changed by the total
+//! amount at once.
+//!
+//! Moving the pointer past an end of the tape that clamps or wraps changes
+//! the cells reached after it, and past an end that errors or can't grow
+//! stops the program right there. The block therefore checks whether all
+//! cells are on the tape when running, and runs the original operations
+//! otherwise. The lowest and highest cell the pointer reaches are kept in the
+//! block for this, even if they don't change.
+//!
+//! # Optimization requirements
+//! - Cells must wrap, as a cell may saturate or trap before the total amount
+//!   is added otherwise.
+//! - Neighbouring add, subtract, seek or block operations, of which at least
+//!   two add, subtract or block.
+//!
+//! # Example operations
+//! - `>+>++<<-`
+//!     - Add -1, 1 and 2 to the current and next two cells
+//! - `+>->`
+//!     - Add 1 and -1 to the current and next cell
+//!     - Seek 2 cells
+
+
+
+use std::collections::BTreeMap;
+
+use super::super::super::{Arithmetic, Node, Op, Options, Span};
+
+
+
+/// Optimize runs of add and seek operations, into blocks.
+///
+/// This optimization is applied on a vector of operations, given to `ops`.
+/// The `options` the program is run with must use wrapping arithmetic.
+/// The operations are optimized in place, `true` is returned if they changed.
+pub fn optimize_block(ops: &mut Vec<Node>, options: &Options) -> bool {
+    // Do not run if cells don't wrap
+    if options.arithmetic != Arithmetic::Wrap {
+        return false;
+    }
+
+    let mut result: Vec<Node> = Vec::with_capacity(ops.len());
+    let mut run: Vec<Node> = vec![];
+    let mut changed = false;
+
+    for node in ops.drain(..) {
+        match node.op {
+            Op::Seek(_) | Op::Inc(_) | Op::IncAt(..) | Op::Block(..) => run.push(node),
+            _ => {
+                changed |= collapse(&mut run, &mut result);
+                result.push(node);
+            },
+        }
+    }
+    changed |= collapse(&mut run, &mut result);
+
+    *ops = result;
+    changed
+}
+
+/// Collapse the given `run` of add and seek operations into a block, and push
+/// it onto `ops`. The block keeps the run as its original operations. Runs
+/// with fewer than two add operations are pushed as is.
+///
+/// The run is left empty. `true` is returned if it was collapsed.
+fn collapse(run: &mut Vec<Node>, ops: &mut Vec<Node>) -> bool {
+    if run.iter().filter(|node| !matches!(node.op, Op::Seek(_))).count() < 2 {
+        ops.append(run);
+        return false;
+    }
+
+    // Sum the amount added to each cell, relative to the pointer at the start.
+    // Track the lowest and highest offset the pointer reaches.
+    let mut deltas = BTreeMap::new();
+    let mut offset = 0;
+    let (mut low, mut high) = (0, 0);
+    for node in run.iter() {
+        match node.op {
+            Op::Seek(amount) => offset += amount,
+            Op::Inc(amount) => *deltas.entry(offset).or_insert(0) += amount,
+            Op::IncAt(at, amount) => *deltas.entry(offset + at).or_insert(0) += amount,
+            Op::Block(ref block, seek, _) => {
+                for &(at, amount) in block {
+                    *deltas.entry(offset + at).or_insert(0) += amount;
+                }
+                offset += seek;
+            },
+            _ => unreachable!(),
+        }
+        low = low.min(offset);
+        high = high.max(offset);
+    }
+    for &at in deltas.keys() {
+        low = low.min(at);
+        high = high.max(at);
+    }
+
+    // Keep the lowest and highest cell, so they are checked to be on the tape.
+    // The current cell and the cell sought to are always checked.
+    deltas.entry(low).or_insert(0);
+    deltas.entry(high).or_insert(0);
+    let deltas = deltas.into_iter()
+        .filter(|&(at, amount)| amount != 0 || ((at == low || at == high) && at != 0 && at != offset))
+        .collect();
+    let span = run.iter()
+        .map(|node| node.span)
+        .fold(run[0].span, Span::join);
+    ops.push(Node::new(
+        Op::Block(deltas, offset, Box::new(Op::Routine(run.split_off(0), false))),
+        span,
+    ));
+    true
+}
@@ -0,0 +1,142 @@
+//! # Canonicalize optimization
+//! This optimization removes operations without effect, and fuses
+//! neighbouring operations again after other optimizations have run.
+//!
+//! Interpreting programs such as `><` or `+-` leaves operations without effect,
+//! and removing or replacing loops may leave operations next to each other
+//! that could have been merged.
+//!
+//! Operations changing a cell that is overwritten right after, are dead
+//! stores. These are removed as well.
+//!
+//! Unless cells wrap, a cell may saturate or trap between two operations.
+//! Additions of opposite sign, additions to a cell that was set, and dead
+//! stores are then kept. Likewise, seeks of opposite direction are kept if
+//! the pointer may not move exactly in between, see `Options::exact`, and
+//! adding nothing to a relative cell is kept if it may not be reached exactly.
+//!
+//! The source ranges of removed operations are joined into the neighbouring
+//! operations.
+//!
+//! This is a peephole optimization, applied on neighbouring operations.
+//!
+//! # Optimization requirements
+//! - An operation without effect, or two neighbouring operations that can be
+//!   fused into one.
+//!
+//! # Example operations
+//! - `+-`
+//!     - Nothing
+//! - `>[-]<+`, where the next cell is known to be zero
+//!     - Increase the current cell
+//! - `+++[-]`
+//!     - Zero the current cell
+//! - `[-]+-`
+//!     - Zero the current cell
+
+
+
+use super::super::super::{Arithmetic, Node, Op, Options, Span};
+
+
+
+/// Optimize operations, by removing those without effect, and fusing
+/// neighbours.
+///
+/// This optimization is applied on a vector of operations, given to `ops`.
+/// The `options` the program is run with select which operations are fused.
+/// The operations are optimized in place, `true` is returned if they changed.
+pub fn optimize_canonicalize(ops: &mut Vec<Node>, options: &Options) -> bool {
+    let mut result: Vec<Node> = Vec::with_capacity(ops.len());
+    let mut changed = false;
+
+    // The source range of removed operations, joined into the next operation
+    let mut removed: Option<Span> = None;
+
+    for mut node in ops.drain(..) {
+        // Remove operations without effect
+        if no_op(&node.op, options) {
+            removed = Some(removed.map_or(node.span, |span| span.join(node.span)));
+            changed = true;
+            continue;
+        }
+        if let Some(span) = removed.take() {
+            node.span = node.span.join(span);
+        }
+
+        // Fuse with the preceding operation, which may leave no operation at
+        // all, after which the operations around it may be fused
+        let fused = result.last().and_then(|last| fuse(&last.op, &node.op, options));
+        if let Some(op) = fused {
+            let span = result.pop().unwrap().span.join(node.span);
+            if no_op(&op, options) {
+                removed = Some(span);
+            } else {
+                result.push(Node::new(op, span));
+            }
+            changed = true;
+            continue;
+        }
+
+        result.push(node);
+    }
+
+    // Join removed operations at the end into the last operation
+    if let (Some(span), Some(last)) = (removed, result.last_mut()) {
+        last.span = last.span.join(span);
+    }
+
+    *ops = result;
+    changed
+}
+
+/// Check whether the given operation has no effect, for a program run with
+/// the given `options`.
+fn no_op(op: &Op, options: &Options) -> bool {
+    match *op {
+        Op::Seek(0) | Op::Inc(0) => true,
+        Op::IncAt(offset, 0) => options.exact(None, offset),
+        Op::Routine(ref ops, false) => ops.is_empty(),
+        Op::Load(_, ref values) => values.is_empty(),
+        Op::Write(ref bytes) => bytes.is_empty(),
+        Op::Block(ref deltas, 0, _) => deltas.is_empty(),
+        _ => false,
+    }
+}
+
+/// Fuse the given operations, `first` followed by `second`, into a single
+/// operation, for a program run with the given `options`.
+///
+/// `None` is returned if they can't be fused.
+fn fuse(first: &Op, second: &Op, options: &Options) -> Option<Op> {
+    // Unless cells wrap, a cell may saturate or trap at the first operation
+    let wrap = options.arithmetic == Arithmetic::Wrap;
+    let op = match (first, second) {
+        // Merge moving the pointer, and adding to the same cell
+        (&Op::Seek(a), &Op::Seek(b)) if a.signum() == b.signum() || options.exact(None, a) => Op::Seek(a + b),
+        (&Op::Inc(a), &Op::Inc(b)) if wrap || a.signum() == b.signum() => Op::Inc(a + b),
+        (&Op::IncAt(at, a), &Op::IncAt(offset, b)) if at == offset && (wrap || a.signum() == b.signum()) =>
+            Op::IncAt(at, a + b),
+
+        // Add to a cell that was set
+        (&Op::Zero, &Op::Inc(amount)) => Op::Set(amount),
+        (&Op::Set(value), &Op::Inc(amount)) if wrap => Op::Set(value + amount),
+        (&Op::SetAt(at, value), &Op::IncAt(offset, amount)) if wrap && at == offset => Op::SetAt(at, value + amount),
+
+        // Remove dead stores to a cell that is set
+        (&Op::Zero, &Op::Zero) => Op::Zero,
+        (&Op::Zero, &Op::Set(value)) => Op::Set(value),
+        (&Op::Inc(_), &Op::Zero) | (&Op::Set(_), &Op::Zero) if wrap => Op::Zero,
+        (&Op::Inc(_), &Op::Set(value)) | (&Op::Set(_), &Op::Set(value)) if wrap => Op::Set(value),
+        (&Op::IncAt(at, _), &Op::SetAt(offset, value)) | (&Op::SetAt(at, _), &Op::SetAt(offset, value))
+            if wrap && at == offset => Op::SetAt(offset, value),
+
+        _ => return None,
+    };
+
+    // Zero cells with the zero operation
+    match op {
+        Op::Set(0) => Some(Op::Zero),
+        op => Some(op),
+    }
+}
@@ -0,0 +1,172 @@
+//! # Seek deferring optimization
+//! This optimization defers moving the memory pointer in straight-line code,
+//! until an operation that depends on the pointer is reached.
+//!
+//! Operations in between act on a cell relative to the pointer instead, so
+//! each run of straight-line code moves the pointer at most once.
+//!
+//! Loops depend on the pointer, as their condition reads the current cell.
+//! The deferred seek is therefore committed before any routine, and at the
+//! end of the operations.
+//!
+//! Moving the pointer past an end of the tape that clamps or wraps changes
+//! the cells reached after it. Seeks are therefore only deferred while the
+//! pointer moves exactly, see `Options::exact`.
+//!
+//! Moving the pointer past an end that errors or can't grow stops the program
+//! right there. Acting on that cell stops the program the same way, so seeks
+//! past such an end are still deferred if the next operation acts on the cell
+//! the pointer moves to. The cell is then reached at the same point, growing
+//! the tape or failing like the seek would.
+//!
+//! # Optimization requirements
+//! - Seek operations, followed by operations that may act on a relative cell.
+//! - The pointer must move exactly, or past an end that errors or grows,
+//!   followed by an operation acting on the cell it moves to.
+//!
+//! # Example operations
+//! - `>+>+<<`
+//!     - Increase the next cell
+//!     - Increase the 2nd next cell
+//! - `>>.<+>`
+//!     - Output the 2nd next cell
+//!     - Increase the next cell
+//!     - Seek to the 2nd next cell
+//! - `>+<<[-<]`
+//!     - Increase the next cell
+//!     - Seek to the previous cell
+//!     - Run the routine
+//! - `>[-]>+`
+//!     - Zero the next cell
+//!     - Increase the 2nd next cell
+//!     - Seek to the 2nd next cell
+
+
+
+use super::super::super::{Boundary, Node, Op, Options, Span};
+
+
+
+/// Optimize seeks, by deferring them.
+///
+/// This optimization is applied on a vector of operations, given to `ops`.
+/// The `options` the program is run with define the ends of the tape.
+/// The operations are optimized in place, `true` is returned if they changed.
+pub fn optimize_defer_seek(ops: &mut Vec<Node>, options: &Options) -> bool {
+    // Create a vector for the optimized operations, and remember the deferred
+    // seek along with its source range and the number of seeks it combines
+    let mut result = Vec::with_capacity(ops.len());
+    let mut deferred: Option<(isize, Span, usize)> = None;
+    let mut changed = false;
+
+    // Whether the deferred seek moves past an end that errors or grows, which
+    // the next operation must act on the cell of
+    let mut touch = false;
+
+    for node in ops.drain(..) {
+        // Defer seeks, combining them. If the pointer may not move exactly,
+        // and not past an end that errors or grows, commit the deferred seek
+        // followed by this one instead
+        if let Op::Seek(amount) = node.op {
+            if touch {
+                changed |= commit(&mut deferred, &mut result);
+            }
+            let offset = deferred.map_or(0, |(offset, _, _)| offset) + amount;
+            touch = !options.exact(None, offset);
+            if touch && !stops(offset, options) {
+                changed |= commit(&mut deferred, &mut result);
+                result.push(node);
+                touch = false;
+                continue;
+            }
+            deferred = Some(match deferred {
+                Some((offset, span, count)) => (offset + amount, span.join(node.span), count + 1),
+                None => (amount, node.span, 1),
+            });
+            continue;
+        }
+
+        // Act on the relative cell if possible, commit the seek otherwise.
+        // Past an end that errors or grows, the operation must act on the
+        // current cell
+        let offset = deferred.map_or(0, |(offset, _, _)| offset);
+        let shifted = match node.op {
+            Op::Inc(_) | Op::Input | Op::Output | Op::Zero | Op::Set(_) => shift(&node.op, offset),
+            _ if touch => None,
+            _ => shift(&node.op, offset),
+        };
+        touch = false;
+        match shifted {
+            Some(op) => {
+                changed |= offset != 0;
+                result.push(Node::new(op, node.span));
+            },
+            None => {
+                changed |= commit(&mut deferred, &mut result);
+                result.push(node);
+            },
+        }
+    }
+
+    // Commit the last deferred seek
+    changed |= commit(&mut deferred, &mut result);
+
+    *ops = result;
+    changed
+}
+
+/// Check whether moving the pointer by the relative `offset` past an end of the
+/// tape stops the program or grows the tape, for a program run with the given
+/// `options`. Acting on the cell at that offset then does the same.
+fn stops(offset: isize, options: &Options) -> bool {
+    let boundary = if offset < 0 { options.tape_left } else { options.tape_right };
+    boundary == Boundary::Error || boundary == Boundary::Grow
+}
+
+/// Shift the given operation to act on the cell at the relative `offset`.
+///
+/// `None` is returned if the operation can't act on a relative cell.
+fn shift(op: &Op, offset: isize) -> Option<Op> {
+    let (current, op) = match *op {
+        Op::Inc(amount) => (offset, Op::IncAt(offset, amount)),
+        Op::IncAt(current, amount) => (current + offset, Op::IncAt(current + offset, amount)),
+        Op::Input => (offset, Op::InputAt(offset)),
+        Op::InputAt(current) => (current + offset, Op::InputAt(current + offset)),
+        Op::Output => (offset, Op::OutputAt(offset)),
+        Op::OutputAt(current) => (current + offset, Op::OutputAt(current + offset)),
+        Op::Zero => (offset, Op::SetAt(offset, 0)),
+        Op::Set(amount) => (offset, Op::SetAt(offset, amount)),
+        Op::SetAt(current, amount) => (current + offset, Op::SetAt(current + offset, amount)),
+        Op::Load(current, ref values) => (current + offset, Op::Load(current + offset, values.clone())),
+        Op::Write(ref bytes) => return Some(Op::Write(bytes.clone())),
+        _ => return None,
+    };
+
+    // Act on the current cell directly, without an offset
+    if current != 0 {
+        return Some(op);
+    }
+    Some(match op {
+        Op::IncAt(_, amount) => Op::Inc(amount),
+        Op::InputAt(_) => Op::Input,
+        Op::OutputAt(_) => Op::Output,
+        Op::SetAt(_, 0) => Op::Zero,
+        Op::SetAt(_, amount) => Op::Set(amount),
+        op => op,
+    })
+}
+
+/// Commit the given deferred seek to `ops`, if it moves the pointer.
+///
+/// `true` is returned if this changed the seeks it combines.
+fn commit(deferred: &mut Option<(isize, Span, usize)>, ops: &mut Vec<Node>) -> bool {
+    match deferred.take() {
+        Some((offset, span, count)) => {
+            if offset != 0 {
+                ops.push(Node::new(Op::Seek(offset), span));
+            }
+            offset == 0 || count > 1
+        },
+        None => false,
+    }
+}
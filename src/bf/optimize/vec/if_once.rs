@@ -0,0 +1,128 @@
+//! # If optimization
+//! This optimization replaces loops that run at most once with a conditional
+//! routine that doesn't loop.
+//!
+//! A loop runs at most once if its body always ends with the current cell
+//! being zero, for example because it zeroes the cell, or because it ends with
+//! an other loop on the same cell.
+//!
+//! # Optimization requirements
+//! - A conditional routine.
+//! - The last operation in the routine that affects the cell the routine ends
+//!   at must leave it zero.
+//! - Operations after it must reach cells exactly, see `Options::exact`.
+//!   Moving the pointer past an end of the tape that clamps, wraps or can't
+//!   grow changes the cell the routine ends at.
+//!
+//! # Example routines
+//! - `[.[-]]`
+//!     - Output the current cell, if it isn't zero
+//!     - Zero the current cell
+//! - `[>+<[-]]`
+//!     - Increase the next cell, if the current cell isn't zero
+//!     - Zero the current cell
+//! - `[->+>[>]]`
+//!     - Decrease the current cell, if it isn't zero
+//!     - Increase the next cell
+//!     - Scan to a zero cell
+
+
+
+use super::super::super::{Node, Op, Options};
+
+
+
+/// Optimize routines that run at most once.
+///
+/// This optimization is applied on a vector of operations, given to `ops`.
+/// The operations are optimized in place, `true` is returned if they changed.
+pub fn optimize_if(ops: &mut Vec<Node>, options: &Options) -> bool {
+    // Do not run if there are no routines to optimize
+    if !ops.iter().any(|node| match node.op {
+        Op::Routine(ref body, true) => ends_zero(body, options),
+        _ => false,
+    }) {
+        return false;
+    }
+
+    // Replace the routines by conditional routines that don't loop
+    *ops = ops.drain(..)
+        .map(|node| match node.op {
+            Op::Routine(body, true) if ends_zero(&body, options) => Node::new(Op::If(body), node.span),
+            op => Node::new(op, node.span),
+        })
+        .collect();
+
+    true
+}
+
+/// Check whether the given operations always end with the current cell being
+/// zero, for a program run with the given `options`.
+fn ends_zero(ops: &[Node], options: &Options) -> bool {
+    // Walk back from the end, tracking the offset of the cell the operations
+    // end at, relative to the pointer at each operation
+    let mut offset = 0;
+    for node in ops.iter().rev() {
+        // Operations leaving the current cell zero, wherever the pointer is
+        if offset == 0 {
+            match node.op {
+                Op::Zero | Op::Set(0) | Op::Scan(_) | Op::AddAndZero(..) | Op::Product(..) | Op::DivMod(..)
+                    | Op::Routine(_, true) | Op::If(_) => return true,
+                _ => {},
+            }
+        }
+
+        // Other operations must reach all cells exactly to track the offset
+        let (low, high) = extent(&node.op);
+        if !options.exact(None, low) || !options.exact(None, high) {
+            return false;
+        }
+
+        match node.op {
+            // Operations leaving the cell zero
+            Op::SetAt(at, 0) if at == offset => return true,
+
+            // Operations changing the cell
+            Op::Zero | Op::Set(_) | Op::Inc(_) | Op::Input if offset == 0 => return false,
+            Op::SetAt(at, _) | Op::IncAt(at, _) | Op::InputAt(at) if at == offset => return false,
+            Op::AddAndZero(_, ref targets, _) if targets.iter().any(|&(at, _)| at == offset) => return false,
+            Op::Product(_, ref first, _, _) if first.iter().any(|&(at, _, _)| at == offset) => return false,
+            Op::Block(ref deltas, seek, _) if deltas.iter().any(|&(at, _)| at == offset + seek) => return false,
+            Op::Load(at, ref values) if offset >= at && offset < at + values.len() as isize =>
+                return values[(offset - at) as usize] == 0,
+
+            // Operations moving the pointer
+            Op::Seek(amount) | Op::Block(_, amount, _) => offset += amount,
+            Op::Scan(_) | Op::DivMod(..) | Op::Routine(..) | Op::If(_) => return false,
+
+            // Operations not affecting the cell
+            _ => {},
+        }
+    }
+
+    false
+}
+
+/// Determine the lowest and highest offset relative to the pointer, at which
+/// the given operation reaches memory cells or moves the pointer to.
+fn extent(op: &Op) -> (isize, isize) {
+    let offsets: Vec<isize> = match *op {
+        Op::Seek(amount) => vec![amount],
+        Op::SetAt(at, _) | Op::IncAt(at, _) | Op::InputAt(at) | Op::OutputAt(at) => vec![at],
+        Op::AddAndZero(_, ref targets, _) => targets.iter().map(|&(at, _)| at).collect(),
+        Op::Block(ref deltas, seek, _) => deltas.iter().map(|&(at, _)| at).chain(Some(seek)).collect(),
+        Op::Product(_, ref first, _, _) => first.iter()
+            .flat_map(|&(target, _, ref terms)| terms.iter().map(|&(at, _)| at).chain(Some(target)))
+            .collect(),
+        Op::DivMod(divisor, ref targets, _) => targets.iter()
+            .map(|&(at, _)| at)
+            .chain(vec![divisor, divisor + 4 * divisor.signum()])
+            .collect(),
+        Op::Load(at, ref values) => vec![at, at + values.len() as isize - 1],
+        _ => vec![],
+    };
+    (
+        offsets.iter().cloned().min().unwrap_or(0).min(0),
+        offsets.iter().cloned().max().unwrap_or(0).max(0),
+    )
+}
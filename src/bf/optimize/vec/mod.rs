@@ -0,0 +1,12 @@
+mod block;
+mod canonicalize;
+mod defer_seek;
+mod if_once;
+mod set;
+
+// Reexport
+pub use self::block::optimize_block;
+pub use self::canonicalize::optimize_canonicalize;
+pub use self::defer_seek::optimize_defer_seek;
+pub use self::if_once::optimize_if;
+pub use self::set::optimize_set;
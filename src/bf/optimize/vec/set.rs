@@ -0,0 +1,63 @@
+//! # Set optimization
+//! This optimization fuses zeroing the current cell, followed by adding to or
+//! subtracting from it, into a single operation that sets the cell value.
+//!
+//! Cells are commonly initialized this way.
+//!
+//! Adding to a cell that was set to a value other than zero is only fused if
+//! cells wrap, as the cell may saturate or trap in between otherwise.
+//!
+//! This is a peephole optimization, applied on neighbouring operations.
+//!
+//! # Optimization requirements
+//! - A zero or set operation, followed by an add or subtract operation.
+//!
+//! # Example operations
+//! - `[-]+++++`
+//!     - Set the current cell to 5
+//! - `[-]--`
+//!     - Set the current cell to -2, which wraps around
+//! - `[-]++[-]+`
+//!     - Set the current cell to 2
+//!     - Set the current cell to 1
+
+
+
+use super::super::super::{Arithmetic, Node, Op, Options};
+
+
+
+/// Optimize zero and add operations, by setting the cell value.
+///
+/// This optimization is applied on a vector of operations, given to `ops`.
+/// The `options` the program is run with select whether set operations are
+/// added to.
+/// The operations are optimized in place, `true` is returned if they changed.
+pub fn optimize_set(ops: &mut Vec<Node>, options: &Options) -> bool {
+    let mut result: Vec<Node> = Vec::with_capacity(ops.len());
+    let mut changed = false;
+
+    for node in ops.drain(..) {
+        // Fuse an addition into a preceding zero or set operation
+        if let Op::Inc(amount) = node.op {
+            if let Some(last) = result.last_mut() {
+                let value = match last.op {
+                    Op::Zero => Some(amount),
+                    Op::Set(current) if options.arithmetic == Arithmetic::Wrap => Some(current + amount),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    last.op = Op::Set(value);
+                    last.span = last.span.join(node.span);
+                    changed = true;
+                    continue;
+                }
+            }
+        }
+
+        result.push(node);
+    }
+
+    *ops = result;
+    changed
+}
@@ -1,4 +1,8 @@
+use std::str::FromStr;
+
 use super::tty_read::ReaderOptions;
+use super::memory::MEM_SIZE;
+use super::{Cell, CellWidth};
 
 
 
@@ -18,6 +22,58 @@ pub struct Options {
 
     /// Prettify described program logic.
     pub pretty: bool,
+
+    /// What the input operation does when the end of input is reached.
+    pub eof: Eof,
+
+    /// The width of memory cells to run programs with.
+    pub cell_width: CellWidth,
+
+    /// How memory cell values overflow.
+    pub arithmetic: Arithmetic,
+
+    /// The number of memory cells on the tape.
+    pub tape_size: usize,
+
+    /// Grow the tape on demand up to this number of memory cells, at ends
+    /// with a growing boundary.
+    /// The tape has a fixed size if `None`, and grows without limit if
+    /// `usize::MAX`.
+    pub tape_limit: Option<usize>,
+
+    /// What happens when the memory pointer moves past the left end.
+    pub tape_left: Boundary,
+
+    /// What happens when the memory pointer moves past the right end.
+    pub tape_right: Boundary,
+
+    /// The number of operations to execute before suspending the program.
+    /// Executing operations, and looping a routine, each take one unit.
+    /// Programs run until they finish if `None`.
+    pub fuel: Option<u64>,
+
+    /// The optimization level, selecting the default optimization passes.
+    /// See `PassManager`.
+    pub opt_level: u8,
+
+    /// Names of optimization passes to run, regardless of the level.
+    pub enable_passes: Vec<String>,
+
+    /// Names of optimization passes not to run, regardless of the level.
+    pub disable_passes: Vec<String>,
+
+    /// Programs run on fresh memory, as created by `Memory::from_options`.
+    /// Optimizations may then rely on all cells being zero at the start.
+    /// Programs may run on memory in any state if `false`.
+    pub fresh_memory: bool,
+
+    /// Only optimize in ways that keep the program behaviour exactly the same,
+    /// including routines that loop forever under the cell width.
+    pub strict: bool,
+
+    /// Run the unoptimized program in lockstep, and stop at the first
+    /// operation the optimized program diverges at. See `Program::verify`.
+    pub verify: bool,
 }
 
 impl Options {
@@ -34,6 +90,140 @@ impl Options {
             profile,
             describe,
             pretty,
+            eof: Eof::Unchanged,
+            cell_width: CellWidth::Bits8,
+            arithmetic: Arithmetic::Wrap,
+            tape_size: MEM_SIZE,
+            tape_limit: None,
+            tape_left: Boundary::Clamp,
+            tape_right: Boundary::Grow,
+            fuel: None,
+            opt_level: 2,
+            enable_passes: vec![],
+            disable_passes: vec![],
+            fresh_memory: false,
+            strict: false,
+            verify: false,
+        }
+    }
+
+    /// Check whether the memory cell at the relative `offset` from the pointer
+    /// is reached exactly, without clamping, wrapping, an out of bounds error
+    /// or failing to grow at an end of the tape.
+    ///
+    /// The `position` of the pointer relative to the cell it started at is
+    /// used if known. Otherwise the cell is only reached exactly if the end in
+    /// the direction of the offset grows without limit.
+    ///
+    /// Growing the tape changes where the other end wraps to, so an end only
+    /// grows exactly if the other end doesn't wrap.
+    pub(crate) fn exact(&self, position: Option<isize>, offset: isize) -> bool {
+        let exact = |boundary, other| {
+            boundary == Boundary::Grow && other != Boundary::Wrap && self.tape_limit == Some(usize::MAX)
+        };
+        let (left, right) = (exact(self.tape_left, self.tape_right), exact(self.tape_right, self.tape_left));
+        match position {
+            Some(position) => {
+                let target = position + offset;
+                (target >= 0 || left) && (target < self.tape_size.max(1) as isize || right)
+            },
+            None => (offset >= 0 || left) && (offset <= 0 || right),
+        }
+    }
+}
+
+/// The behaviour of the input operation when the end of input is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eof {
+    /// Leave the current memory cell unchanged.
+    Unchanged,
+
+    /// Store zero in the current memory cell.
+    Zero,
+
+    /// Store -1 in the current memory cell, all bits set.
+    MinusOne,
+}
+
+impl Eof {
+    /// Get the value a memory cell with the `current` value should have,
+    /// after reading input while at the end of input.
+    pub fn value<C: Cell>(self, current: C) -> C {
+        match self {
+            Eof::Unchanged => current,
+            Eof::Zero => C::default(),
+            Eof::MinusOne => C::MAX,
+        }
+    }
+}
+
+impl FromStr for Eof {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Eof, ()> {
+        match name {
+            "unchanged" => Ok(Eof::Unchanged),
+            "zero" => Ok(Eof::Zero),
+            "minus-one" => Ok(Eof::MinusOne),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How memory cell values overflow, when they go past their minimum or maximum
+/// value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Wrap around, to the other end of the value range.
+    Wrap,
+
+    /// Saturate, stopping at the minimum or maximum value.
+    Saturate,
+
+    /// Stop the program with an error.
+    Trap,
+}
+
+impl FromStr for Arithmetic {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Arithmetic, ()> {
+        match name {
+            "wrap" => Ok(Arithmetic::Wrap),
+            "saturate" => Ok(Arithmetic::Saturate),
+            "trap" => Ok(Arithmetic::Trap),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What happens when the memory pointer moves past an end of the tape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// Clamp the pointer, keeping it at the end.
+    Clamp,
+
+    /// Wrap the pointer around, to the other end.
+    Wrap,
+
+    /// Stop the program with an error.
+    Error,
+
+    /// Grow the tape on demand, up to the tape limit.
+    /// Stop the program with an error if the tape can't grow.
+    Grow,
+}
+
+impl FromStr for Boundary {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Boundary, ()> {
+        match name {
+            "clamp" => Ok(Boundary::Clamp),
+            "wrap" => Ok(Boundary::Wrap),
+            "error" => Ok(Boundary::Error),
+            "grow" => Ok(Boundary::Grow),
+            _ => Err(()),
         }
     }
 }
@@ -0,0 +1,550 @@
+use std::io::{Read, Write};
+
+use super::{Cell, Exit, Interpreter, Memory, Node, Op, Options, ParseError, PassManager, RunError, Suspension};
+use super::verify::verify;
+
+
+
+/// A compiled brainfuck program.
+///
+/// The program source is interpreted and optimized once into an operation
+/// tree, after which it may be run any number of times against caller
+/// supplied memory, created with the options the program is run with.
+#[derive(Debug)]
+pub struct Program {
+    /// The root routine of the program.
+    root: Node,
+}
+
+impl Program {
+    /// Compile the given brainfuck program `source`.
+    ///
+    /// An error is returned if the program can't be parsed.
+    pub fn compile(source: &str, options: &Options) -> Result<Program, ParseError> {
+        Ok(Program::from_node(
+            Interpreter::interpret(&mut source.bytes(), options)?,
+        ))
+    }
+
+    /// Compile the given brainfuck program `source`, optimized with the given
+    /// `passes` instead of the passes selected in `options`.
+    ///
+    /// An error is returned if the program can't be parsed.
+    pub fn compile_with(
+        source: &str,
+        options: &Options,
+        passes: &PassManager,
+    ) -> Result<Program, ParseError> {
+        Ok(Program::from_node(
+            Interpreter::interpret_with(&mut source.bytes(), options, passes)?,
+        ))
+    }
+
+    /// Wrap an already interpreted operation tree as program.
+    pub fn from_node(root: Node) -> Program {
+        Program {
+            root,
+        }
+    }
+
+    /// Get the root operation of this program.
+    pub fn op(&self) -> &Op {
+        &self.root.op
+    }
+
+    /// Get the root node of this program, including its source range.
+    pub fn node(&self) -> &Node {
+        &self.root
+    }
+
+    /// Run this program from the start on the given `memory`.
+    ///
+    /// The optimized operations depend on the tape and the cell width, so the
+    /// memory must be created with the given `options`, see
+    /// `Memory::from_options`. An error is returned if it isn't.
+    ///
+    /// The given `memory` is used as is, and is left in the state the program
+    /// finished in. If the program was compiled with `Options::fresh_memory`,
+    /// the memory must be fresh as well.
+    /// Program input is read from `input`, program output is written to
+    /// `output`.
+    ///
+    /// If `Options::fuel` is set, the program is suspended once it has
+    /// executed that many operations. It may then be continued with `resume`.
+    pub fn run<C, R, W>(
+        &self,
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        self.resume(&Suspension::start(), memory, options, input, output)
+    }
+
+    /// Resume running this program where it was suspended, with new fuel.
+    ///
+    /// The given `memory` must be the memory the program was suspended with.
+    /// See `run`.
+    pub fn resume<C, R, W>(
+        &self,
+        suspension: &Suspension,
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        if !memory.matches(options) {
+            return Err(RunError::MemoryMismatch);
+        }
+
+        let mut fuel = options.fuel;
+        let exit = self.root.resume(suspension.position(), memory, options, input, output, &mut fuel)?;
+        output.flush()?;
+        Ok(exit)
+    }
+
+    /// Run this program from the start on the given `memory`, while running
+    /// the `unoptimized` program compiled from the same source in lockstep.
+    /// See `run`.
+    ///
+    /// Both runs read the same input, only the output of this program is
+    /// written to `output`. After each operation of this program, the
+    /// unoptimized run catches up to the end of its source range, and the
+    /// output and memory of both runs are compared. The unoptimized program
+    /// must not be optimized, see `PassManager::new`.
+    ///
+    /// Both runs consume fuel like `run` does, see `Options::fuel`. Once
+    /// either run runs out, this program is suspended at its next operation,
+    /// and may be resumed without verifying, see `resume`.
+    ///
+    /// An error is returned at the first divergence, reporting the operation
+    /// of this program and its source range.
+    /// An error is also returned if the memory doesn't match the `options`.
+    pub fn verify<C, R, W>(
+        &self,
+        unoptimized: &Program,
+        memory: &mut Memory<C>,
+        options: &Options,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Exit, RunError>
+        where
+            C: Cell,
+            R: Read,
+            W: Write,
+    {
+        if !memory.matches(options) {
+            return Err(RunError::MemoryMismatch);
+        }
+
+        verify(&self.root, &unoptimized.root, memory, options, input, output)
+    }
+}
+
+
+
+/// Test whether a program suspended repeatedly produces the same output as
+/// running it at once.
+#[test]
+fn test_resume() {
+    let source = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.";
+    let mut options = Options::default(true, false, false, false);
+    let program = Program::compile(source, &options).unwrap();
+
+    let mut memory = Memory::new();
+    let mut expected = vec![];
+    let exit = program.run(&mut memory, &options, &mut &b""[..], &mut expected).unwrap();
+    assert_eq!(exit, Exit::Finished);
+
+    options.fuel = Some(3);
+    let mut memory = Memory::new();
+    let mut output = vec![];
+    let mut exit = program.run(&mut memory, &options, &mut &b""[..], &mut output).unwrap();
+    while let Exit::OutOfFuel(suspension) = exit {
+        exit = program.resume(&suspension, &mut memory, &options, &mut &b""[..], &mut output).unwrap();
+    }
+    assert_eq!(output, expected);
+
+    let program = Program::compile("+[>+<]", &options).unwrap();
+    let exit = program.run(&mut Memory::new(), &options, &mut &b""[..], &mut vec![]).unwrap();
+    match exit {
+        Exit::OutOfFuel(ref suspension) => assert_eq!(suspension.position(), &[1, 0]),
+        Exit::Finished => panic!("program didn't run out of fuel"),
+    }
+}
+
+/// Test whether optimized loops that never end run out of fuel, and keep
+/// running out of fuel when resumed.
+#[test]
+fn test_resume_endless() {
+    let mut options = Options::default(true, false, false, false);
+    options.fuel = Some(100);
+    let program = Program::compile(",[-->+<]", &options).unwrap();
+    assert!(format!("{:?}", program.op()).contains("AddAndZero"));
+
+    let mut memory: Memory = Memory::from_options(&options);
+    let mut exit = program.run(&mut memory, &options, &mut &b"\x01"[..], &mut vec![]).unwrap();
+    for _ in 0..3 {
+        match exit {
+            Exit::OutOfFuel(suspension) =>
+                exit = program.resume(&suspension, &mut memory, &options, &mut &b""[..], &mut vec![]).unwrap(),
+            Exit::Finished => panic!("program didn't run out of fuel"),
+        }
+    }
+    assert!(matches!(exit, Exit::OutOfFuel(_)));
+    assert_eq!(memory.read() % 2, 1);
+
+    // Products loop the same way
+    let program = Program::compile(",>+<[-->[->+>+<<]>>[-<<+>>]<<<]", &options).unwrap();
+    assert!(format!("{:?}", program.op()).contains("Product"));
+    let exit = program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x01"[..], &mut vec![]).unwrap();
+    assert!(matches!(exit, Exit::OutOfFuel(_)));
+
+    // Scans clamped at the left end loop the same way
+    let program = Program::compile(",[<]", &options).unwrap();
+    assert!(format!("{:?}", program.op()).contains("Scan(-1)"));
+    let exit = program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x01"[..], &mut vec![]).unwrap();
+    assert!(matches!(exit, Exit::OutOfFuel(_)));
+
+    // Strictly optimized zeroing routines loop the same way
+    options.strict = true;
+    let program = Program::compile(",[++]", &options).unwrap();
+    assert!(format!("{:?}", program.op()).contains("AddAndZero(2, [], "));
+    let exit = program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x01"[..], &mut vec![]).unwrap();
+    assert!(matches!(exit, Exit::OutOfFuel(_)));
+}
+
+/// Test whether adding and subtracting in a row saturates or traps at the
+/// intermediate value, at each optimization level.
+#[test]
+fn test_arithmetic() {
+    use super::{Arithmetic, MAX_LEVEL};
+
+    let cases = [
+        ("-+", [Some(0), Some(1), None]),
+        ("-[-]+", [Some(1), Some(1), None]),
+        ("[-]-+", [Some(0), Some(1), None]),
+    ];
+    let mut options = Options::default(true, false, false, false);
+    for level in 0..=MAX_LEVEL {
+        options.opt_level = level;
+        for &(source, ref expected) in &cases {
+            for (&arithmetic, &expected) in [Arithmetic::Wrap, Arithmetic::Saturate, Arithmetic::Trap].iter().zip(expected) {
+                options.arithmetic = arithmetic;
+                let program = Program::compile(source, &options).unwrap();
+                let mut memory = Memory::new();
+                match program.run(&mut memory, &options, &mut &b""[..], &mut vec![]) {
+                    Ok(_) => assert_eq!(Some(memory.read()), expected, "{} at level {}", source, level),
+                    Err(RunError::Overflow(_)) => assert_eq!(None, expected, "{} at level {}", source, level),
+                    Err(err) => panic!("unexpected error: {}", err),
+                }
+            }
+        }
+    }
+}
+
+/// Test whether zeroing routines that overflow before reaching zero trap, at
+/// each optimization level.
+#[test]
+fn test_trap_zero() {
+    use super::{Arithmetic, MAX_LEVEL};
+
+    let mut options = Options::default(true, false, false, false);
+    options.arithmetic = Arithmetic::Trap;
+    for level in 0..=MAX_LEVEL {
+        options.opt_level = level;
+        for &source in &[",[+]", ",[++]"] {
+            let program = Program::compile(source, &options).unwrap();
+            match program.run(&mut Memory::new(), &options, &mut &b"\x01"[..], &mut vec![]) {
+                Err(RunError::Overflow(_)) => {},
+                result => panic!("{} didn't trap at level {}: {:?}", source, level, result),
+            }
+        }
+    }
+}
+
+/// Test whether seeking past a clamping end of the tape reaches the same cells
+/// at each optimization level.
+#[test]
+fn test_clamp() {
+    use super::MAX_LEVEL;
+
+    let cases = [
+        ("<+>+<<++>.", [3, 1, 0]),
+        ("<+->+", [0, 1, 0]),
+        ("<>+", [0, 1, 0]),
+        ("+[<+>-]<", [1, 0, 0]),
+        (">++>+++[-<[-<+<+>>]<<[->>+<<]>>>]", [0, 3, 4]),
+    ];
+    let mut options = Options::default(true, false, false, false);
+    for level in 0..=MAX_LEVEL {
+        options.opt_level = level;
+        for &(source, expected) in &cases {
+            let program = Program::compile(source, &options).unwrap();
+            let mut memory = Memory::new();
+            program.run(&mut memory, &options, &mut &b""[..], &mut vec![]).unwrap();
+            assert_eq!(memory.cells()[..3], expected, "{} at level {}", source, level);
+        }
+    }
+}
+
+/// Test whether growing the tape at one end changes where the other end wraps
+/// to, at each optimization level.
+#[test]
+fn test_wrap_grow() {
+    use super::{Boundary, MAX_LEVEL};
+
+    let cases: [(&str, Boundary, Boundary, &[u8]); 3] = [
+        ("+><<-", Boundary::Wrap, Boundary::Grow, &[1, 255]),
+        (">>><<<<<+", Boundary::Wrap, Boundary::Grow, &[0, 0, 1, 0]),
+        ("+<>>-", Boundary::Grow, Boundary::Wrap, &[255, 1]),
+    ];
+    let mut options = Options::default(true, false, false, false);
+    options.tape_size = 1;
+    options.tape_limit = Some(usize::MAX);
+    for level in 0..=MAX_LEVEL {
+        options.opt_level = level;
+        for &(source, left, right, expected) in &cases {
+            options.tape_left = left;
+            options.tape_right = right;
+            let program = Program::compile(source, &options).unwrap();
+            let mut memory: Memory = Memory::from_options(&options);
+            program.run(&mut memory, &options, &mut &b""[..], &mut vec![]).unwrap();
+            assert_eq!(memory.cells(), expected, "{} at level {}", source, level);
+        }
+    }
+}
+
+/// Test whether loops moving the pointer past a clamping end of the tape
+/// loop again, like the unoptimized program, at each optimization level.
+#[test]
+fn test_if_clamp() {
+    use super::{Boundary, MAX_LEVEL};
+
+    let cases = [
+        ("+>+<[[-]<>.]", 30000, Boundary::Clamp, Boundary::Grow),
+        (",>,[-[---]>+.<]", 2, Boundary::Clamp, Boundary::Clamp),
+        (",[-<[--]<>.]", 30000, Boundary::Clamp, Boundary::Grow),
+    ];
+    let mut options = Options::default(true, false, false, false);
+    for &(source, size, left, right) in &cases {
+        options.tape_size = size;
+        options.tape_left = left;
+        options.tape_right = right;
+        let mut expected = vec![];
+        options.opt_level = 0;
+        let program = Program::compile(source, &options).unwrap();
+        program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x05\x09"[..], &mut expected).unwrap();
+
+        for level in 1..=MAX_LEVEL {
+            options.opt_level = level;
+            let program = Program::compile(source, &options).unwrap();
+            let mut output = vec![];
+            program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x05\x09"[..], &mut output).unwrap();
+            assert_eq!(output, expected, "{} at level {}", source, level);
+        }
+    }
+}
+
+/// Test whether seeking past an end of the tape that errors or can't grow
+/// fails the program before any output, reporting the first cell past that
+/// end, at each optimization level.
+#[test]
+fn test_out_of_bounds() {
+    use super::{Boundary, MAX_LEVEL};
+
+    let cases = [
+        ("<>+.", 8, Boundary::Error, Boundary::Grow, -1),
+        ("<<+.", 8, Boundary::Error, Boundary::Grow, -1),
+        ("<[]>+.", 8, Boundary::Error, Boundary::Grow, -1),
+        ("+[<+>-].", 8, Boundary::Error, Boundary::Grow, -1),
+        (">++>+++[-<[-<+<+>>]<<[->>+<<]>>>].", 8, Boundary::Error, Boundary::Grow, -1),
+        (">>><<<+.", 2, Boundary::Clamp, Boundary::Grow, 2),
+        (">>><<<+.", 2, Boundary::Clamp, Boundary::Error, 2),
+    ];
+    let mut options = Options::default(true, false, false, false);
+    options.fresh_memory = true;
+    for level in 0..=MAX_LEVEL {
+        options.opt_level = level;
+        for &(source, size, left, right, expected) in &cases {
+            options.tape_size = size;
+            options.tape_left = left;
+            options.tape_right = right;
+            let program = Program::compile(source, &options).unwrap();
+            let mut output = vec![];
+            let mut memory: Memory = Memory::from_options(&options);
+            match program.run(&mut memory, &options, &mut &b""[..], &mut output) {
+                Err(RunError::OutOfBounds(index, _)) => {
+                    assert!(output.is_empty(), "{} at level {}", source, level);
+                    assert_eq!(index, expected, "{} at level {}", source, level);
+                },
+                _ => panic!("{} didn't go out of bounds at level {}", source, level),
+            }
+        }
+    }
+}
+
+/// Test whether evaluating the start of a program at compile time keeps the
+/// program output, and only happens for programs run on fresh memory.
+#[test]
+fn test_partial_eval() {
+    use super::Boundary;
+
+    let source = "++++++++[>++++++++<-]>+.+.<,[>.<-]";
+    let mut options = Options::default(true, false, false, false);
+    options.tape_left = Boundary::Grow;
+    options.tape_limit = Some(usize::MAX);
+    options.opt_level = 3;
+    let program = Program::compile(source, &options).unwrap();
+    assert!(!format!("{:?}", program.op()).contains("Load"));
+
+    options.fresh_memory = true;
+    let program = Program::compile(source, &options).unwrap();
+    assert_eq!(
+        format!("{:?}", program.op()),
+        "Routine([Load(1, [66]), Write([65, 66]), Input, Routine([OutputAt(1), Inc(-1)], true)], false)",
+    );
+
+    let mut output = vec![];
+    program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"\x02"[..], &mut output).unwrap();
+    assert_eq!(output, b"ABBB");
+
+    // Memory not created with the same options is refused
+    match program.run(&mut Memory::new(), &options, &mut &b"\x02"[..], &mut vec![]) {
+        Err(RunError::MemoryMismatch) => {},
+        result => panic!("memory didn't mismatch: {:?}", result),
+    }
+
+    // Evaluation stops before a loop that never ends
+    let program = Program::compile("++.>+[<+>++]", &options).unwrap();
+    assert_eq!(
+        format!("{:?}", program.op()),
+        "Routine([Load(0, [2, 1]), Write([2]), Seek(1), AddAndZero(2, [(-1, 1)], Routine([Seek(-1), Inc(1), Seek(1), Inc(2)], true))], false)",
+    );
+
+    // Evaluation stops before growing the tape, which the loaded cells don't
+    options.tape_size = 1;
+    options.tape_left = Boundary::Wrap;
+    options.tape_limit = Some(12);
+    let mut expected = vec![];
+    options.opt_level = 0;
+    let program = Program::compile(">.<,--<.>", &options).unwrap();
+    program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"F"[..], &mut expected).unwrap();
+    options.opt_level = 3;
+    let program = Program::compile(">.<,--<.>", &options).unwrap();
+    let mut output = vec![];
+    program.run(&mut Memory::<u8>::from_options(&options), &options, &mut &b"F"[..], &mut output).unwrap();
+    assert_eq!(output, expected);
+}
+
+/// Test whether optimized division and modulo routines behave like the
+/// unoptimized routine, also when the cells aren't laid out as expected and
+/// when suspended.
+#[test]
+fn test_verify_div_mod() {
+    let source = ">>>>>>,>,>,>,>,<<<<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>.>.>.>.>.";
+    let mut options = Options::default(true, false, false, false);
+    let unoptimized = Program::compile_with(source, &options, &PassManager::new()).unwrap();
+    let program = Program::compile(source, &options).unwrap();
+    assert!(format!("{:?}", program.op()).contains("DivMod"));
+
+    let inputs: &[&[u8]] = &[
+        b"\xc8\x07\0\0\0",
+        b"\xff\x02\0\0\0",
+        b"\x0d\x04\x02\x09\0",
+        b"\x09\0\0\0\0",
+        b"\x0a\x01\0\0\0",
+        b"\x05\x03\0\0\x01",
+    ];
+    for input in inputs {
+        let mut output = vec![];
+        options.fuel = None;
+        program.verify(&unoptimized, &mut Memory::new(), &options, &mut &input[..], &mut output).unwrap();
+        assert_eq!(output.len(), 5);
+
+        // Running the routine step by step may be suspended as well
+        options.fuel = Some(7);
+        let (mut memory, mut input, mut resumed) = (Memory::new(), &input[..], vec![]);
+        let mut exit = program.run(&mut memory, &options, &mut input, &mut resumed).unwrap();
+        while let Exit::OutOfFuel(suspension) = exit {
+            exit = program.resume(&suspension, &mut memory, &options, &mut input, &mut resumed).unwrap();
+        }
+        assert_eq!(resumed, output);
+    }
+}
+
+/// Test whether verifying runs out of fuel like running does, and may be
+/// resumed without verifying.
+#[test]
+fn test_verify_fuel() {
+    let mut options = Options::default(true, false, false, false);
+    options.fuel = Some(1000);
+    options.strict = true;
+    for &source in &["+[]", ",[-->+<]", "+[>+]"] {
+        let unoptimized = Program::compile_with(source, &options, &PassManager::new()).unwrap();
+        let program = Program::compile(source, &options).unwrap();
+        let exit = program.verify(&unoptimized, &mut Memory::new(), &options, &mut &b"\x01"[..], &mut vec![]).unwrap();
+        assert!(matches!(exit, Exit::OutOfFuel(_)), "{}", source);
+    }
+
+    let source = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.";
+    let unoptimized = Program::compile_with(source, &options, &PassManager::new()).unwrap();
+    let program = Program::compile(source, &options).unwrap();
+    let mut expected = vec![];
+    program.run(&mut Memory::new(), &options, &mut &b""[..], &mut expected).unwrap();
+
+    options.fuel = Some(20);
+    let (mut memory, mut output) = (Memory::new(), vec![]);
+    let mut exit = program.verify(&unoptimized, &mut memory, &options, &mut &b""[..], &mut output).unwrap();
+    assert!(matches!(exit, Exit::OutOfFuel(_)));
+    while let Exit::OutOfFuel(suspension) = exit {
+        exit = program.resume(&suspension, &mut memory, &options, &mut &b""[..], &mut output).unwrap();
+    }
+    assert_eq!(output, expected);
+}
+
+/// Test whether verifying optimizations reports the operation an optimized
+/// program diverged at.
+#[test]
+fn test_verify() {
+    use super::{DivergenceKind, RoutinePass};
+
+    let source = ",>+++[<++>-]<[-]+.";
+    let options = Options::default(true, false, false, false);
+    let unoptimized = Program::compile_with(source, &options, &PassManager::new()).unwrap();
+    let program = Program::compile(source, &options).unwrap();
+    let mut output = vec![];
+    let exit = program.verify(&unoptimized, &mut Memory::new(), &options, &mut &b"\x05"[..], &mut output).unwrap();
+    assert_eq!((exit, output), (Exit::Finished, b"\x01".to_vec()));
+
+    // Misoptimize clear loops, setting the cell to one instead
+    let mut passes = PassManager::new();
+    passes.add(Box::new(RoutinePass {
+        name: "broken",
+        level: 0,
+        optimize: |_, ops, _| match ops.len() {
+            1 => Some(Op::Set(1)),
+            _ => None,
+        },
+    }));
+    let program = Program::compile_with(source, &options, &passes).unwrap();
+    match program.verify(&unoptimized, &mut Memory::new(), &options, &mut &b"\x05"[..], &mut vec![]) {
+        Err(RunError::Diverged(divergence)) => {
+            assert!(matches!(divergence.kind, DivergenceKind::Cell(0)));
+            let (op, span) = divergence.op.unwrap();
+            assert_eq!(op, "Set(1)");
+            assert_eq!((span.start.offset, span.end.offset), (13, 16));
+        },
+        result => panic!("program didn't diverge: {:?}", result),
+    }
+}
@@ -0,0 +1,564 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::slice;
+
+use super::op::consume;
+use super::{Boundary, Cell, Exit, Memory, Node, Op, Options, RunError, Span, Suspension};
+
+
+
+/// The maximum number of unoptimized operations to execute, to catch up with a
+/// single optimized operation.
+const BUDGET: u64 = 100_000_000;
+
+
+
+/// A divergence between running an optimized program, and running the same
+/// program unoptimized.
+#[derive(Debug)]
+pub struct Divergence {
+    /// What diverged.
+    pub kind: DivergenceKind,
+
+    /// The description and source range of the optimized operation the runs
+    /// diverged at.
+    /// `None` if they diverged before executing any optimized operation.
+    pub op: Option<(String, Span)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.op {
+            Some((ref op, span)) => write!(f, "after {} at {}: {}", op, span, self.kind),
+            None => write!(f, "at the start: {}", self.kind),
+        }
+    }
+}
+
+/// The way an optimized program run diverged from the unoptimized run.
+#[derive(Debug)]
+pub enum DivergenceKind {
+    /// The output differs, starting at the given byte index.
+    Output(usize),
+
+    /// The value of the memory cell at the given index differs.
+    /// The index is relative to the cell the pointer started at.
+    Cell(isize),
+
+    /// The memory pointer ended at a different cell.
+    Pointer,
+
+    /// The unoptimized run didn't reach the end of the operation.
+    Flow,
+
+    /// Only the optimized run failed, with the given error.
+    OptimizedError(Box<RunError>),
+
+    /// Only the unoptimized run failed, with the given error.
+    UnoptimizedError(Box<RunError>),
+}
+
+impl fmt::Display for DivergenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DivergenceKind::Output(index) =>
+                write!(f, "output differs at byte {}", index),
+            DivergenceKind::Cell(index) =>
+                write!(f, "memory cell {} differs", index),
+            DivergenceKind::Pointer =>
+                write!(f, "memory pointer differs"),
+            DivergenceKind::Flow =>
+                write!(f, "unoptimized program didn't reach the end of the operation"),
+            DivergenceKind::OptimizedError(ref err) =>
+                write!(f, "only the optimized program failed, {}", err),
+            DivergenceKind::UnoptimizedError(ref err) =>
+                write!(f, "only the unoptimized program failed, {}", err),
+        }
+    }
+}
+
+/// Run the `optimized` operation tree, while running the `unoptimized` tree
+/// of the same program in lockstep, on the same input.
+///
+/// See `Program::verify`.
+pub(crate) fn verify<C, R, W>(
+    optimized: &Node,
+    unoptimized: &Node,
+    memory: &mut Memory<C>,
+    options: &Options,
+    input: &mut R,
+    output: &mut W,
+) -> Result<Exit, RunError>
+    where
+        C: Cell,
+        R: Read,
+        W: Write,
+{
+    // Both runs read the same input, recorded once
+    let recording = RefCell::new(Recording {
+        input,
+        bytes: vec![],
+    });
+
+    let mut code = vec![];
+    flatten(slice::from_ref(unoptimized), &mut code);
+    let mut verifier = Verifier {
+        options,
+        shadow: Shadow {
+            code,
+            pc: 0,
+            memory: memory.clone(),
+            input: Replay::new(&recording),
+            output: vec![],
+            fuel: options.fuel,
+        },
+        memory,
+        input: Replay::new(&recording),
+        output: Tee {
+            output,
+            bytes: vec![],
+        },
+        fuel: options.fuel,
+        touched: BTreeSet::new(),
+        checked: 0,
+        back_edge: false,
+        last: None,
+    };
+
+    let exit = verifier.run(optimized)?;
+    if exit == Exit::Finished {
+        verifier.finish()?;
+    }
+    verifier.output.flush()?;
+    Ok(exit)
+}
+
+/// An instruction of an unoptimized program, flattened into a list of
+/// instructions.
+#[derive(Clone, Copy)]
+enum Instr<'a> {
+    /// Execute the operation of the node.
+    Node(&'a Node),
+
+    /// Start a loop, jump to the given index if the current memory cell is
+    /// zero.
+    Open(usize),
+
+    /// End a loop, jump to the given index if the current memory cell isn't
+    /// zero.
+    Close(usize),
+}
+
+/// Flatten the given operations into `code`, along with the source offset
+/// each instruction starts at.
+fn flatten<'a>(ops: &'a [Node], code: &mut Vec<(Instr<'a>, usize)>) {
+    for node in ops {
+        match node.op {
+            Op::Routine(ref body, true) => {
+                let open = code.len();
+                code.push((Instr::Open(0), node.span.start.offset));
+                flatten(body, code);
+                let close = code.len();
+                code.push((Instr::Close(open + 1), node.span.end.offset - 1));
+                code[open].0 = Instr::Open(close + 1);
+            },
+            Op::Routine(ref body, false) => flatten(body, code),
+            _ => code.push((Instr::Node(node), node.span.start.offset)),
+        }
+    }
+}
+
+/// Determine the largest distance from the pointer, at which operations in
+/// the tree with the given `root` access memory cells or move the pointer to.
+fn reach(root: &Node) -> isize {
+    match root.op {
+        Op::Routine(ref ops, _) | Op::If(ref ops) => ops.iter().map(reach).max().unwrap_or(0),
+        Op::SetAt(offset, _) | Op::IncAt(offset, _) | Op::InputAt(offset) | Op::OutputAt(offset) =>
+            offset.abs(),
+        Op::AddAndZero(_, ref targets, _) =>
+            targets.iter().map(|&(offset, _)| offset.abs()).max().unwrap_or(0),
+        Op::Block(ref targets, seek, _) => targets.iter()
+            .map(|&(offset, _)| offset.abs())
+            .chain(Some(seek.abs()))
+            .max()
+            .unwrap_or(0),
+        Op::Product(_, ref first, _, _) => first.iter()
+            .flat_map(|&(target, _, ref terms)| terms.iter().map(|&(offset, _)| offset).chain(Some(target)))
+            .map(isize::abs)
+            .max()
+            .unwrap_or(0),
+        Op::DivMod(divisor, ref targets, _) => targets.iter()
+            .map(|&(offset, _)| offset.abs())
+            .chain(Some((divisor + 4 * divisor.signum()).abs()))
+            .max()
+            .unwrap_or(0),
+        Op::Load(offset, ref values) => offset.abs().max((offset + values.len() as isize - 1).abs()),
+        _ => 0,
+    }
+}
+
+/// Get the value of the memory cell at the given `index`, relative to the
+/// cell the pointer started at. Cells that don't exist are zero.
+fn cell<C: Cell>(memory: &Memory<C>, index: isize) -> C {
+    let index = index + memory.origin() as isize;
+    if index < 0 {
+        return C::default();
+    }
+    memory.cells().get(index as usize).cloned().unwrap_or_default()
+}
+
+/// Get the index of the memory cell pointer, relative to the cell it started
+/// at.
+fn position<C: Cell>(memory: &Memory<C>) -> isize {
+    memory.pointer() as isize - memory.origin() as isize
+}
+
+/// Get the index of the first memory cell on the tape, and the index after the
+/// last, relative to the cell the pointer started at.
+fn bounds<C: Cell>(memory: &Memory<C>) -> (isize, isize) {
+    let start = -(memory.origin() as isize);
+    (start, start + memory.cells().len() as isize)
+}
+
+/// Program input, read once and recorded for multiple runs.
+struct Recording<'a, R: 'a> {
+    /// The input to read from.
+    input: &'a mut R,
+
+    /// The bytes read so far.
+    bytes: Vec<u8>,
+}
+
+/// A run reading recorded program input.
+struct Replay<'a, 'b: 'a, R: 'b> {
+    /// The recorded input.
+    recording: &'a RefCell<Recording<'b, R>>,
+
+    /// The index of the next byte to read.
+    position: usize,
+}
+
+impl<'a, 'b, R> Replay<'a, 'b, R> {
+    /// Start reading the given recorded input.
+    fn new(recording: &'a RefCell<Recording<'b, R>>) -> Replay<'a, 'b, R> {
+        Replay {
+            recording,
+            position: 0,
+        }
+    }
+}
+
+impl<'a, 'b, R: Read> Read for Replay<'a, 'b, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Read and record a new byte if this run is ahead
+        let mut recording = self.recording.borrow_mut();
+        if self.position == recording.bytes.len() {
+            let mut byte = [0];
+            if recording.input.read(&mut byte)? == 0 {
+                return Ok(0);
+            }
+            recording.bytes.push(byte[0]);
+        }
+
+        buf[0] = recording.bytes[self.position];
+        self.position += 1;
+        Ok(1)
+    }
+}
+
+/// Program output, written to the actual output and recorded.
+struct Tee<'a, W: 'a> {
+    /// The actual output.
+    output: &'a mut W,
+
+    /// The bytes written so far.
+    bytes: Vec<u8>,
+}
+
+impl<'a, W: Write> Write for Tee<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write_all(buf)?;
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// The unoptimized run, executing flattened instructions.
+struct Shadow<'a, 'b: 'a, 'c: 'b, C: Cell, R: 'c> {
+    /// The flattened instructions.
+    code: Vec<(Instr<'a>, usize)>,
+
+    /// The index of the next instruction to execute.
+    pc: usize,
+
+    /// The memory of this run.
+    memory: Memory<C>,
+
+    /// The input of this run.
+    input: Replay<'b, 'c, R>,
+
+    /// The output of this run.
+    output: Vec<u8>,
+
+    /// The fuel left for this run, see `Options::fuel`.
+    fuel: Option<u64>,
+}
+
+impl<'a, 'b, 'c, C: Cell, R: Read> Shadow<'a, 'b, 'c, C, R> {
+    /// Execute the next instruction.
+    ///
+    /// Operations and entering loops consume one unit of fuel, as does
+    /// looping again, like in the optimized run. `false` is returned without
+    /// executing if there is no fuel left.
+    fn step(&mut self, options: &Options) -> Result<bool, RunError> {
+        let costs = match self.code[self.pc].0 {
+            Instr::Node(_) | Instr::Open(_) => true,
+            Instr::Close(_) => !self.memory.zero(),
+        };
+        if costs && !consume(&mut self.fuel) {
+            return Ok(false);
+        }
+
+        self.pc = match self.code[self.pc].0 {
+            Instr::Node(node) => {
+                node.op.execute(&mut self.memory, options, &mut self.input, &mut self.output, &mut None)
+                    .map_err(|err| err.at(node.span))?;
+                self.pc + 1
+            },
+            Instr::Open(end) if self.memory.zero() => end,
+            Instr::Close(start) if !self.memory.zero() => start,
+            _ => self.pc + 1,
+        };
+        Ok(true)
+    }
+}
+
+/// An optimized run, checked against an unoptimized run.
+struct Verifier<'a, 'b: 'a, 'c: 'b, C: Cell + 'a, R: 'c, W: 'a> {
+    /// The options the program is run with.
+    options: &'a Options,
+
+    /// The unoptimized run.
+    shadow: Shadow<'a, 'b, 'c, C, R>,
+
+    /// The memory of the optimized run.
+    memory: &'a mut Memory<C>,
+
+    /// The input of the optimized run.
+    input: Replay<'b, 'c, R>,
+
+    /// The output of the optimized run.
+    output: Tee<'a, W>,
+
+    /// The fuel left for the optimized run, see `Options::fuel`.
+    fuel: Option<u64>,
+
+    /// The indices of the cells the last operation touched in either run,
+    /// relative to the cell the pointer started at.
+    touched: BTreeSet<isize>,
+
+    /// The number of optimized output bytes checked.
+    checked: usize,
+
+    /// Whether an optimized routine looped since the last checked operation.
+    back_edge: bool,
+
+    /// The last optimized operation executed.
+    last: Option<&'a Node>,
+}
+
+impl<'a, 'b, 'c, C: Cell, R: Read, W: Write> Verifier<'a, 'b, 'c, C, R, W> {
+    /// Run the given optimized `node`, checking each operation it executes.
+    ///
+    /// Fuel is consumed like `Node::execute` does. If it runs out, the
+    /// suspension to resume the optimized run at is returned.
+    fn run(&mut self, node: &'a Node) -> Result<Exit, RunError> {
+        // Consume fuel, suspend before executing if there is none left
+        if !consume(&mut self.fuel) {
+            return Ok(Exit::OutOfFuel(Suspension::at(vec![], Some(node.span))));
+        }
+
+        let (ops, cond, looping) = match node.op {
+            Op::Routine(ref ops, cond) => (ops, cond, cond),
+            Op::If(ref ops) => (ops, true, false),
+            _ => return self.check(node),
+        };
+
+        if cond && self.memory.zero() {
+            return Ok(Exit::Finished);
+        }
+        loop {
+            for (index, inner) in ops.iter().enumerate() {
+                if let Exit::OutOfFuel(suspension) = self.run(inner)? {
+                    return Ok(Exit::OutOfFuel(suspension.within(index).at_span(node.span)));
+                }
+            }
+            if !looping || self.memory.zero() {
+                return Ok(Exit::Finished);
+            }
+
+            // Looping consumes fuel as well, suspend at the condition
+            if !consume(&mut self.fuel) {
+                return Ok(Exit::OutOfFuel(Suspension::at(vec![ops.len()], Some(node.span))));
+            }
+            self.back_edge = true;
+        }
+    }
+
+    /// Execute the given optimized operation `node`, let the unoptimized run
+    /// catch up, and compare both.
+    ///
+    /// Nothing is compared if the optimized run runs out of fuel halfway the
+    /// operation, or if the unoptimized run runs out of fuel catching up. The
+    /// optimized run is then suspended at its next operation.
+    fn check(&mut self, node: &'a Node) -> Result<Exit, RunError> {
+        let before = position(self.memory);
+        let result = node.op.execute(self.memory, self.options, &mut self.input, &mut self.output, &mut self.fuel)
+            .map_err(|err| err.at(node.span));
+        self.last = Some(node);
+        if let Ok(Exit::OutOfFuel(suspension)) = result {
+            return Ok(Exit::OutOfFuel(suspension.at_span(node.span)));
+        }
+
+        // Touch the cells the operation may have accessed
+        let reach = reach(node);
+        for offset in -reach..=reach {
+            let index = self.reached(before + offset);
+            self.touched.insert(index);
+        }
+        self.touched.insert(position(self.memory));
+
+        // Fail if either run failed, and the other didn't
+        let back_edge = mem::replace(&mut self.back_edge, false);
+        match (result, self.catch_up(node.span.end.offset, back_edge)) {
+            (Err(err), Err(DivergenceKind::UnoptimizedError(_))) | (Err(err), Ok(false)) => return Err(err),
+            (Err(err), _) => return Err(self.diverged(DivergenceKind::OptimizedError(Box::new(err)))),
+            (Ok(_), Err(kind)) => return Err(self.diverged(kind)),
+            (Ok(_), Ok(false)) => {
+                self.fuel = Some(0);
+                return Ok(Exit::Finished);
+            },
+            (Ok(_), Ok(true)) => {},
+        }
+
+        // The optimized output must be a prefix of the unoptimized output
+        let index = (self.checked..self.output.bytes.len())
+            .find(|&index| self.shadow.output.get(index) != Some(&self.output.bytes[index]));
+        if let Some(index) = index {
+            return Err(self.diverged(DivergenceKind::Output(index)));
+        }
+        self.checked = self.output.bytes.len();
+
+        let touched = mem::take(&mut self.touched);
+        self.compare_cells(touched.into_iter())?;
+        Ok(Exit::Finished)
+    }
+
+    /// Let the unoptimized run catch up to the source offset `end`, at which
+    /// the last optimized operation ended.
+    ///
+    /// The unoptimized run executes up to the first instruction at or after
+    /// `end`, unless it is there already. If an optimized routine looped,
+    /// the unoptimized run must loop as well, and first executes up to and
+    /// including a jump back.
+    ///
+    /// `false` is returned if the unoptimized run ran out of fuel first.
+    fn catch_up(&mut self, end: usize, back_edge: bool) -> Result<bool, DivergenceKind> {
+        let target = self.shadow.code.partition_point(|&(_, start)| start < end);
+        let mut looping = back_edge;
+        let mut budget = BUDGET;
+        while looping || self.shadow.pc < target {
+            if self.shadow.pc == self.shadow.code.len() || budget == 0 {
+                return Err(DivergenceKind::Flow);
+            }
+            let pc = self.shadow.pc;
+            let stepped = self.shadow.step(self.options)
+                .map_err(|err| DivergenceKind::UnoptimizedError(Box::new(err)))?;
+            if !stepped {
+                return Ok(false);
+            }
+            self.touched.insert(position(&self.shadow.memory));
+            looping &= self.shadow.pc > pc;
+            budget -= 1;
+        }
+        Ok(true)
+    }
+
+    /// Finish the unoptimized run, and compare the final state of both runs.
+    /// Nothing is compared if the unoptimized run runs out of fuel first.
+    fn finish(&mut self) -> Result<(), RunError> {
+        match self.catch_up(usize::MAX, false) {
+            Ok(true) => {},
+            Ok(false) => return Ok(()),
+            Err(kind) => return Err(self.diverged(kind)),
+        }
+
+        let (optimized, unoptimized) = (&self.output.bytes, &self.shadow.output);
+        if optimized != unoptimized {
+            let index = optimized.iter()
+                .zip(unoptimized)
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| optimized.len().min(unoptimized.len()));
+            return Err(self.diverged(DivergenceKind::Output(index)));
+        }
+
+        // Compare all cells on either tape
+        let (a, b) = (bounds(self.memory), bounds(&self.shadow.memory));
+        self.compare_cells(a.0.min(b.0)..a.1.max(b.1))?;
+        if position(self.memory) != position(&self.shadow.memory) {
+            return Err(self.diverged(DivergenceKind::Pointer));
+        }
+        Ok(())
+    }
+
+    /// Determine the cell the optimized run reaches at the given `index`,
+    /// relative to the cell the pointer started at, following a clamping or
+    /// wrapping end of the tape.
+    fn reached(&self, index: isize) -> isize {
+        let (start, end) = bounds(self.memory);
+        let boundary = if index < start {
+            self.options.tape_left
+        } else if index >= end {
+            self.options.tape_right
+        } else {
+            return index;
+        };
+        match boundary {
+            Boundary::Clamp => index.max(start).min(end - 1),
+            Boundary::Wrap => start + (index - start).rem_euclid(end - start),
+            Boundary::Error | Boundary::Grow => index,
+        }
+    }
+
+    /// Compare the memory cells of both runs at the given `indices`, relative
+    /// to the cell the pointer started at.
+    fn compare_cells<I>(&self, mut indices: I) -> Result<(), RunError>
+        where
+            I: Iterator<Item = isize>,
+    {
+        match indices.find(|&index| cell(self.memory, index) != cell(&self.shadow.memory, index)) {
+            Some(index) => Err(self.diverged(DivergenceKind::Cell(index))),
+            None => Ok(()),
+        }
+    }
+
+    /// Create the error reporting a divergence of the given `kind`, at the
+    /// last optimized operation.
+    fn diverged(&self, kind: DivergenceKind) -> RunError {
+        RunError::Diverged(Divergence {
+            kind,
+            op: self.last.map(|node| (format!("{:?}", node.op), node.span)),
+        })
+    }
+}
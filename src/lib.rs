@@ -0,0 +1,50 @@
+//! # brainfuck-rs-quick
+//! A quick brainfuck interpreter, usable as library.
+//!
+//! A brainfuck program is compiled once into a `Program`, which may then be
+//! run any number of times against caller supplied `Memory`, created with the
+//! same `Options`.
+//!
+//! ```rust
+//! use brainfuck_rs_quick::{Exit, Memory, Options, Program};
+//!
+//! let options = Options::default(true, false, false, false);
+//! let program = Program::compile("++++++++[>++++++++<-]>+.", &options).unwrap();
+//!
+//! let mut memory: Memory = Memory::from_options(&options);
+//! let mut output = vec![];
+//! let exit = program.run(&mut memory, &options, &mut &b""[..], &mut output).unwrap();
+//! assert_eq!(exit, Exit::Finished);
+//! assert_eq!(output, b"A");
+//! ```
+
+pub mod bf;
+pub mod profiler;
+
+// Reexport
+pub use bf::{
+    bf,
+    Arithmetic,
+    BfError,
+    Boundary,
+    Cell,
+    CellWidth,
+    Divergence,
+    DivergenceKind,
+    Eof,
+    Exit,
+    Interpreter,
+    Memory,
+    Node,
+    Op,
+    Options,
+    ParseError,
+    Pass,
+    PassManager,
+    Position,
+    Program,
+    RunError,
+    Span,
+    Sum,
+    Suspension,
+};
@@ -1,14 +1,17 @@
+extern crate brainfuck_rs_quick;
+
 mod app;
 mod arg_handler;
-mod bf;
-mod profiler;
+mod term_input;
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
 
 use arg_handler::ArgHandler;
-use bf::{bf, Options};
-use profiler::Profiler;
+use brainfuck_rs_quick::{Cell, CellWidth, Exit, Memory, Options, PassManager, Program, RunError};
+use brainfuck_rs_quick::profiler::Profiler;
+use term_input::TermInput;
 
 
 
@@ -19,14 +22,106 @@ fn main() {
     let options = arg_handler.as_options();
 
     // Read the file
-    let program = read_file(arg_handler.file(), &options);
+    let source = read_file(arg_handler.file(), &options);
+
+    // Compile the program, report parse errors
+    let program = match Program::compile(&source, &options) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("error: failed to parse '{}': {}", arg_handler.file(), err);
+            process::exit(1);
+        },
+    };
+
+    // Describe program logic
+    if options.describe {
+        if options.pretty {
+            println!("{:#?}", program.op());
+        } else {
+            println!("{:?}", program.op());
+        }
+    }
+
+    // Compile the program without optimizations as well, to verify against
+    let unoptimized = if options.verify {
+        Program::compile_with(&source, &options, &PassManager::new()).ok()
+    } else {
+        None
+    };
+
+    // Run the program with the selected memory cell type, and profile
+    let mut profiler = Profiler::new(options.profile);
+    let unoptimized = unoptimized.as_ref();
+    let result = match options.cell_width {
+        CellWidth::Bits8 => run::<u8>(&program, unoptimized, &options),
+        CellWidth::Bits16 => run::<u16>(&program, unoptimized, &options),
+        CellWidth::Bits32 => run::<u32>(&program, unoptimized, &options),
+        CellWidth::Bits64 => run::<u64>(&program, unoptimized, &options),
+    };
+    if options.profile {
+        profiler.report("Executing");
+    }
 
-    // Run the program
-    let output = bf(&program, &options);
+    // Report runtime errors, and running out of fuel
+    match result {
+        Ok(Exit::Finished) => {},
+        Ok(Exit::OutOfFuel(suspension)) => {
+            eprintln!("error: program ran out of fuel, {}", suspension);
+            process::exit(1);
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+/// Run the program on new memory with cells of type `C`.
+///
+/// Input is read from the terminal byte by byte, or from piped input.
+/// Output is buffered until the program finishes if requested.
+/// If an `unoptimized` program is given, the program is verified against it.
+fn run<C: Cell>(
+    program: &Program,
+    unoptimized: Option<&Program>,
+    options: &Options,
+) -> Result<Exit, RunError> {
+    let mut memory: Memory<C> = Memory::from_options(options);
+    let mut input: Box<dyn Read> = if io::stdin().is_terminal() {
+        Box::new(TermInput::new(&options.reader_options))
+    } else {
+        Box::new(io::stdin())
+    };
+    let stdout = io::stdout();
+
+    // Run unbuffered
+    if !options.buffer {
+        return execute(program, unoptimized, &mut memory, options, &mut input, &mut stdout.lock());
+    }
+
+    // Run buffered, print the buffered output as is
+    let mut output = vec![];
+    let exit = execute(program, unoptimized, &mut memory, options, &mut input, &mut output)?;
+    let mut stdout = stdout.lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+
+    Ok(exit)
+}
 
-    // Print the output
-    if arg_handler.buffer() {
-        println!("{}", output);
+/// Run the program on the given `memory`, or verify it against the
+/// `unoptimized` program if given.
+fn execute<C: Cell, R: Read, W: Write>(
+    program: &Program,
+    unoptimized: Option<&Program>,
+    memory: &mut Memory<C>,
+    options: &Options,
+    input: &mut R,
+    output: &mut W,
+) -> Result<Exit, RunError> {
+    match unoptimized {
+        Some(unoptimized) => program.verify(unoptimized, memory, options, input, output),
+        None => program.run(memory, options, input, output),
     }
 }
 
@@ -0,0 +1,41 @@
+extern crate tty_read;
+
+use std::io::{self, Read};
+
+use self::tty_read::{ReaderOptions, TermReader};
+
+
+
+/// Program input read from the terminal, byte by byte.
+///
+/// This allows a program to read user input without waiting for the user to
+/// press return.
+pub struct TermInput<'a> {
+    /// Terminal reader options.
+    options: &'a ReaderOptions,
+}
+
+impl<'a> TermInput<'a> {
+    /// Create terminal input, using the given reader options.
+    pub fn new(options: &'a ReaderOptions) -> TermInput<'a> {
+        TermInput {
+            options,
+        }
+    }
+}
+
+impl<'a> Read for TermInput<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Read a single byte from the terminal
+        buf[0] = TermReader::open_stdin(self.options)
+            .map_err(|_| io::Error::other("failed to open user input reader"))?
+            .read_byte()
+            .map_err(|_| io::Error::other("failed to read user input"))?;
+
+        Ok(1)
+    }
+}